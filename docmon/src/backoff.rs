@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff with jitter.
+///
+/// Each delay doubles the previous one up to `max` and is then randomized
+/// into `[delay / 2, delay]` so that many waiters do not retry in lockstep.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay();
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = delay / 2;
+        half + rand::thread_rng().gen_range(Duration::default(), half + Duration::from_nanos(1))
+    }
+
+    fn delay(&self) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempt.min(31));
+        self.initial
+            .checked_mul(factor)
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_grows_exponentially_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(10));
+
+        for expected in &[1, 2, 4, 8, 10, 10] {
+            let expected = Duration::from_secs(*expected);
+            let delay = backoff.next_delay();
+            assert!(delay >= expected / 2 && delay <= expected, "{:?}", delay);
+        }
+
+        assert_eq!(backoff.attempt(), 6);
+    }
+
+    #[test]
+    fn it_starts_over_after_reset() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_secs(10));
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+
+        backoff.reset();
+
+        assert!(backoff.next_delay() <= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,371 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde::Deserialize;
+use tokio::sync::Notify;
+
+/// Creates a bounded multi-producer single-consumer channel which applies
+/// `overflow` policy when an item is sent to a full channel.
+pub fn channel<D>(config: ChannelConfig) -> (Sender<D>, Receiver<D>) {
+    let (capacity, overflow) = config.into_parts();
+    let shared = Arc::new(Shared {
+        items: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        overflow,
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        stop: Arc::new(Stop::default()),
+        dropped: AtomicU64::new(0),
+        item_available: Notify::new(),
+        space_available: Notify::new(),
+    });
+
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+struct Shared<D> {
+    items: Mutex<VecDeque<D>>,
+    capacity: usize,
+    overflow: Overflow,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+    stop: Arc<Stop>,
+    dropped: AtomicU64,
+    item_available: Notify,
+    space_available: Notify,
+}
+
+impl<D> Shared<D> {
+    fn items(&self) -> std::sync::MutexGuard<'_, VecDeque<D>> {
+        self.items.lock().expect("channel lock poisoned")
+    }
+}
+
+pub struct Sender<D>(Arc<Shared<D>>);
+
+impl<D> Sender<D> {
+    /// Queues an item. Returns it back if the receiver is gone or a stop
+    /// was requested.
+    pub async fn send(&self, item: D) -> Result<(), D> {
+        loop {
+            if self.0.closed.load(Ordering::Acquire) || self.0.stop.is_requested() {
+                // a notification wakes a single sender, pass it on to the next
+                self.0.space_available.notify();
+                return Err(item);
+            }
+
+            {
+                let mut items = self.0.items();
+                if items.len() < self.0.capacity {
+                    items.push_back(item);
+                    self.0.item_available.notify();
+                    return Ok(());
+                }
+
+                match self.0.overflow {
+                    Overflow::DropNewest => {
+                        self.0.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    Overflow::DropOldest => {
+                        items.pop_front();
+                        items.push_back(item);
+                        self.0.dropped.fetch_add(1, Ordering::Relaxed);
+                        self.0.item_available.notify();
+                        return Ok(());
+                    }
+                    Overflow::Block => {}
+                }
+            }
+
+            self.0.space_available.notified().await;
+        }
+    }
+
+    /// Rejects new items and lets the receiver finish once the queued items
+    /// are taken, even if other senders are still alive.
+    pub fn stop(&self) {
+        self.0.stop.request();
+        self.0.item_available.notify();
+        self.0.space_available.notify();
+    }
+
+    /// Returns a number of items discarded because the channel was full.
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Returns a number of items waiting in the channel.
+    pub fn queued(&self) -> usize {
+        self.0.items().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.0.capacity
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.0.overflow
+    }
+}
+
+impl<D> Clone for Sender<D> {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::AcqRel);
+        Self(self.0.clone())
+    }
+}
+
+impl<D> Drop for Sender<D> {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.item_available.notify();
+        }
+    }
+}
+
+impl<D> std::fmt::Debug for Sender<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("capacity", &self.0.capacity)
+            .field("overflow", &self.0.overflow)
+            .finish()
+    }
+}
+
+pub struct Receiver<D>(Arc<Shared<D>>);
+
+impl<D> Receiver<D> {
+    /// Receives the next item or `None` when all senders are gone or a stop
+    /// was requested, and the channel is empty.
+    pub async fn recv(&mut self) -> Option<D> {
+        loop {
+            if let Some(item) = self.0.items().pop_front() {
+                self.0.space_available.notify();
+                return Some(item);
+            }
+
+            if self.0.senders.load(Ordering::Acquire) == 0 || self.0.stop.is_requested() {
+                return None;
+            }
+
+            self.0.item_available.notified().await;
+        }
+    }
+
+    /// Returns how many items the channel holds.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity
+    }
+
+    /// Returns the stop signal shared with the senders.
+    pub fn stop(&self) -> Arc<Stop> {
+        self.0.stop.clone()
+    }
+
+    /// Returns a number of items discarded because the channel was full.
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<D> Drop for Receiver<D> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.space_available.notify();
+    }
+}
+
+/// A stop request sent to the receiving side of a channel.
+#[derive(Default)]
+pub struct Stop {
+    requested: AtomicBool,
+    notify: Notify,
+}
+
+impl Stop {
+    fn request(&self) {
+        self.requested.store(true, Ordering::Release);
+        self.notify.notify();
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+
+    /// Resolves once a stop was requested.
+    pub async fn requested(&self) {
+        while !self.is_requested() {
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// What to do with an item sent to a full channel.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    /// Discard the item being sent.
+    DropNewest,
+    /// Discard the oldest queued item to make room.
+    DropOldest,
+    /// Wait until the publisher takes an item.
+    Block,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ChannelConfig {
+    capacity: usize,
+    overflow: Overflow,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10_000,
+            overflow: Overflow::DropOldest,
+        }
+    }
+}
+
+impl ChannelConfig {
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        Self { capacity, overflow }
+    }
+
+    pub fn into_parts(self) -> (usize, Overflow) {
+        (self.capacity.max(1), self.overflow)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time;
+
+    use super::*;
+
+    async fn drain(receiver: &mut Receiver<u32>) -> Vec<u32> {
+        let mut items = Vec::new();
+        while let Some(item) = receiver.recv().await {
+            items.push(item);
+        }
+        items
+    }
+
+    #[tokio::test]
+    async fn it_drops_newest_items_when_full() {
+        let (sender, mut receiver) = channel(ChannelConfig::new(2, Overflow::DropNewest));
+
+        for item in 0..5 {
+            sender.send(item).await.unwrap();
+        }
+        assert_eq!(sender.dropped(), 3);
+        drop(sender);
+
+        assert_eq!(drain(&mut receiver).await, vec![0, 1]);
+    }
+
+    #[tokio::test]
+    async fn it_drops_oldest_items_when_full() {
+        let (sender, mut receiver) = channel(ChannelConfig::new(2, Overflow::DropOldest));
+
+        for item in 0..5 {
+            sender.send(item).await.unwrap();
+        }
+        assert_eq!(receiver.dropped(), 3);
+        drop(sender);
+
+        assert_eq!(drain(&mut receiver).await, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn it_blocks_sender_when_full() {
+        let (sender, mut receiver) = channel(ChannelConfig::new(2, Overflow::Block));
+
+        let task = tokio::spawn(async move {
+            for item in 0..5 {
+                sender.send(item).await.unwrap();
+            }
+        });
+
+        time::delay_for(Duration::from_millis(50)).await;
+        assert_eq!(receiver.0.items().len(), 2);
+
+        assert_eq!(drain(&mut receiver).await, vec![0, 1, 2, 3, 4]);
+        assert_eq!(receiver.dropped(), 0);
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn it_drains_queued_items_after_stop() {
+        let (sender, mut receiver) = channel(ChannelConfig::new(10, Overflow::Block));
+        let other = sender.clone();
+        let stop = receiver.stop();
+
+        sender.send(1).await.unwrap();
+        sender.send(2).await.unwrap();
+        sender.stop();
+        stop.requested().await;
+
+        assert_eq!(other.send(3).await, Err(3));
+        assert_eq!(drain(&mut receiver).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn it_releases_all_blocked_senders_when_receiver_is_gone() {
+        let (sender, receiver) = channel(ChannelConfig::new(1, Overflow::Block));
+        sender.send(0).await.unwrap();
+
+        let tasks = (1..=3)
+            .map(|item| {
+                let sender = sender.clone();
+                tokio::spawn(async move { sender.send(item).await })
+            })
+            .collect::<Vec<_>>();
+        time::delay_for(Duration::from_millis(50)).await;
+        drop(receiver);
+
+        for (item, task) in (1..=3).zip(tasks) {
+            let sent = time::timeout(Duration::from_secs(1), task).await;
+            assert_eq!(sent.unwrap().unwrap(), Err(item));
+        }
+    }
+
+    #[tokio::test]
+    async fn it_releases_all_blocked_senders_on_stop() {
+        let (sender, mut receiver) = channel(ChannelConfig::new(1, Overflow::Block));
+        sender.send(0).await.unwrap();
+
+        let tasks = (1..=3)
+            .map(|item| {
+                let sender = sender.clone();
+                tokio::spawn(async move { sender.send(item).await })
+            })
+            .collect::<Vec<_>>();
+        time::delay_for(Duration::from_millis(50)).await;
+        sender.stop();
+
+        for (item, task) in (1..=3).zip(tasks) {
+            let sent = time::timeout(Duration::from_secs(1), task).await;
+            assert_eq!(sent.unwrap().unwrap(), Err(item));
+        }
+        drop(sender);
+        assert_eq!(drain(&mut receiver).await, vec![0]);
+    }
+
+    #[tokio::test]
+    async fn it_returns_items_when_receiver_is_gone() {
+        let (sender, receiver) = channel(ChannelConfig::new(1, Overflow::Block));
+        drop(receiver);
+
+        assert_eq!(sender.send(1).await, Err(1));
+    }
+}
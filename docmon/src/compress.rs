@@ -0,0 +1,91 @@
+//! gzip and zlib compression of request bodies and rotated files.
+
+use std::io::Write;
+
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression as Level,
+};
+use serde::Deserialize;
+
+/// Compression of HTTP request bodies.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    /// The zlib format, as `Content-Encoding: deflate` means.
+    Deflate,
+}
+
+impl Compression {
+    /// The `Content-Encoding` header value, `None` when not compressed.
+    pub fn content_encoding(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Deflate => Some("deflate"),
+        }
+    }
+
+    pub fn encode(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            Compression::None => data,
+            Compression::Gzip => gzip(&data),
+            Compression::Deflate => zlib(&data),
+        }
+    }
+}
+
+/// Compresses `data` into the gzip format (RFC 1952).
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Level::fast());
+    encoder.write_all(data).expect("write to memory");
+    encoder.finish().expect("write to memory")
+}
+
+/// Compresses `data` into the zlib format (RFC 1950).
+pub fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Level::fast());
+    encoder.write_all(data).expect("write to memory");
+    encoder.finish().expect("write to memory")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{decode, gunzip};
+
+    #[test]
+    fn it_encodes_with_content_encoding() {
+        let data = b"{\"id\":\"0123456789ab\"}".repeat(100);
+
+        for compression in &[Compression::None, Compression::Gzip, Compression::Deflate] {
+            let encoded = compression.encode(data.clone());
+            assert_eq!(decode(compression.content_encoding(), &encoded), data);
+        }
+    }
+
+    #[test]
+    fn it_compresses_repetitive_data() {
+        let data =
+            "docker_container,id=0123456789ab,name=web cpu_percentage=20.5 1588327201000000000\n"
+                .repeat(1000);
+
+        let compressed = gzip(data.as_bytes());
+        assert!(compressed.len() < data.len() / 20);
+        assert_eq!(gunzip(&compressed), data.as_bytes());
+    }
+
+    #[test]
+    fn it_roundtrips_arbitrary_data() {
+        let data = (0..200_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect::<Vec<_>>();
+
+        for data in &[&b""[..], b"a", b"aaaa", &data] {
+            assert_eq!(gunzip(&gzip(data)), *data);
+        }
+    }
+}
@@ -1,14 +1,24 @@
-use std::path::Path;
+use std::{collections::HashSet, path::Path};
 
 use config::{ConfigError, Environment, File};
 use serde::Deserialize;
 
-use crate::{client::ClientConfig, publish::PublisherConfig};
+use crate::{
+    client::ClientConfig,
+    output::{OutputConfig, SinkConfig},
+    publish::PublisherConfig,
+    stats::{CollectorConfig, FilterConfig},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    client: ClientConfig,
-    publisher: PublisherConfig,
+    // a single Data Collector output configured before `outputs` were introduced
+    client: Option<ClientConfig>,
+    publisher: Option<PublisherConfig>,
+    #[serde(default)]
+    outputs: Vec<OutputConfig>,
+    #[serde(default)]
+    collector: CollectorConfig,
 }
 
 impl Config {
@@ -17,11 +27,54 @@ impl Config {
         config.merge(File::from(path.as_ref()))?;
         config.merge(Environment::with_prefix("DOCMON_").separator("__"))?;
 
-        config.try_into()
+        let mut config: Config = config.try_into()?;
+        config.merge_legacy_output()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn into_parts(self) -> (Vec<OutputConfig>, CollectorConfig) {
+        (self.outputs, self.collector)
     }
 
-    pub fn into_parts(self) -> (ClientConfig, PublisherConfig) {
-        (self.client, self.publisher)
+    fn merge_legacy_output(&mut self) -> Result<(), ConfigError> {
+        match (self.client.take(), self.publisher.take()) {
+            (Some(client), Some(publisher)) => {
+                self.outputs.push(OutputConfig::new(
+                    SinkConfig::DataCollector(client),
+                    publisher,
+                    FilterConfig::default(),
+                ));
+                Ok(())
+            }
+            (None, None) => Ok(()),
+            _ => Err(ConfigError::Message(
+                "both client and publisher sections are required".to_string(),
+            )),
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.outputs.is_empty() {
+            return Err(ConfigError::Message("no outputs configured".to_string()));
+        }
+        self.collector.validate()?;
+
+        let mut spools = HashSet::new();
+        for output in &self.outputs {
+            output.validate()?;
+            if let Some(spool) = output.publisher().spool() {
+                if !spools.insert(spool.path()) {
+                    return Err(ConfigError::Message(format!(
+                        "spool path {} is used by more than one output",
+                        spool.path().display()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -29,6 +82,153 @@ impl Config {
 mod tests {
     use super::*;
 
+    fn write_config(content: &str) -> (tempfile::TempDir, std::path::PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn it_reads_multiple_outputs() {
+        let (_dir, path) = write_config(
+            r#"
+            [[outputs]]
+            type = "data_collector"
+            customer_id = "workspace-1"
+            shared_key = "a2V5"
+            log_name = "StatEntries"
+            batch_size = 200
+            interval = 10
+
+            [[outputs]]
+            type = "data_collector"
+            customer_id = "workspace-2"
+            shared_key = "a2V5"
+            log_name = "WebStats"
+            batch_size = 50
+            interval = 5
+
+            [outputs.filter.include]
+            names = ["web-*"]
+
+            [[outputs]]
+            type = "prometheus"
+            listen = "127.0.0.1:9417"
+            "#,
+        );
+
+        let (outputs, _) = Config::from_file(path).unwrap().into_parts();
+
+        assert_eq!(outputs.len(), 3);
+        let mut outputs = outputs.into_iter().skip(1);
+        let (sink, publisher, _) = outputs.next().unwrap().into_parts();
+        assert!(matches!(sink, SinkConfig::DataCollector(_)));
+        let (log_name, batch_size, interval, ..) = publisher.into_parts();
+        assert_eq!(log_name, "WebStats");
+        assert_eq!(batch_size, 50);
+        assert_eq!(interval, std::time::Duration::from_secs(5));
+
+        let (sink, ..) = outputs.next().unwrap().into_parts();
+        match sink {
+            SinkConfig::Prometheus(config) => {
+                let (listen, grace_period) = config.into_parts();
+                assert_eq!(listen, "127.0.0.1:9417");
+                assert_eq!(grace_period, std::time::Duration::from_secs(300));
+            }
+            _ => panic!("unexpected sink"),
+        }
+    }
+
+    #[test]
+    fn it_requires_batching_for_pushed_outputs() {
+        let (_dir, path) = write_config(
+            r#"
+            [[outputs]]
+            type = "data_collector"
+            customer_id = "workspace"
+            shared_key = "a2V5"
+            "#,
+        );
+
+        assert!(Config::from_file(path).is_err());
+    }
+
+    #[test]
+    fn it_reads_legacy_client_and_publisher() {
+        let (_dir, path) = write_config(
+            r#"
+            [client]
+            customer_id = "workspace"
+            shared_key = "a2V5"
+
+            [publisher]
+            log_name = "StatEntries"
+            batch_size = 200
+            interval = 10
+            "#,
+        );
+
+        let (outputs, _) = Config::from_file(path).unwrap().into_parts();
+
+        assert_eq!(outputs.len(), 1);
+    }
+
+    #[test]
+    fn it_rejects_shared_spool_path() {
+        let output = r#"
+            [[outputs]]
+            type = "data_collector"
+            customer_id = "workspace"
+            shared_key = "a2V5"
+            log_name = "StatEntries"
+            batch_size = 200
+            interval = 10
+            [outputs.spool]
+            path = "/tmp/spool"
+            "#;
+        let (_dir, path) = write_config(&output.repeat(2));
+
+        assert!(Config::from_file(path).is_err());
+    }
+
+    #[test]
+    fn it_rejects_zero_reconcile_interval() {
+        let (_dir, path) = write_config(
+            r#"
+            [[outputs]]
+            type = "data_collector"
+            customer_id = "workspace"
+            shared_key = "a2V5"
+            log_name = "StatEntries"
+            batch_size = 200
+            interval = 10
+
+            [collector]
+            reconcile_interval = 0
+            "#,
+        );
+
+        assert!(Config::from_file(path).is_err());
+    }
+
+    #[test]
+    fn it_rejects_zero_output_interval() {
+        let (_dir, path) = write_config(
+            r#"
+            [[outputs]]
+            type = "data_collector"
+            customer_id = "workspace"
+            shared_key = "a2V5"
+            log_name = "StatEntries"
+            batch_size = 200
+            interval = 0
+            "#,
+        );
+
+        assert!(Config::from_file(path).is_err());
+    }
+
     #[test]
     fn it_converts_toml_config() {
         let content = r#"inner_field.field_bar = "value""#.to_string();
@@ -0,0 +1,493 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use flate2::{write::GzEncoder, Compression as Level};
+use futures_util::future::{BoxFuture, FutureExt};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::sink::Sink;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Appends stats records to a file as JSON Lines.
+///
+/// The file is rotated when it grows above `max_size` bytes or gets older
+/// than `max_age`. Rotated files are renamed to `<path>.<timestamp>`,
+/// optionally gzipped, and only the newest `retention` of them are kept.
+pub struct FileWriter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    path: PathBuf,
+    max_size: u64,
+    max_age: Option<Duration>,
+    compress: bool,
+    retention: usize,
+    state: Mutex<State>,
+}
+
+struct State {
+    file: Option<File>,
+    size: u64,
+    opened: SystemTime,
+}
+
+impl FileWriter {
+    pub fn new(config: FileConfig) -> Result<Self> {
+        let (path, max_size, max_age, compress, retention) = config.into_parts();
+        if path.file_name().is_none() {
+            return Err(anyhow!("invalid output file {}", path.display()));
+        }
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create directory {}", dir.display()))?;
+        }
+
+        let inner = Inner {
+            path,
+            max_size,
+            max_age,
+            compress,
+            retention,
+            state: Mutex::new(State {
+                file: None,
+                size: 0,
+                opened: SystemTime::now(),
+            }),
+        };
+        inner.open(&mut inner.state.lock().expect("file lock poisoned"))?;
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Appends one line per record, rotating the file first when it is due.
+    pub fn write(&self, items: &[Value]) -> Result<()> {
+        self.inner
+            .append(&serialize(items)?, items.len(), SystemTime::now())
+    }
+
+    /// Same as `write`, but writes and rotates on the blocking thread pool.
+    pub async fn send(&self, items: &[Value]) -> Result<()> {
+        let data = serialize(items)?;
+        let count = items.len();
+        let inner = self.inner.clone();
+
+        tokio::task::spawn_blocking(move || inner.append(&data, count, SystemTime::now())).await?
+    }
+
+    #[cfg(test)]
+    fn write_at(&self, items: &[Value], now: SystemTime) -> Result<()> {
+        self.inner.append(&serialize(items)?, items.len(), now)
+    }
+}
+
+fn serialize(items: &[Value]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for item in items {
+        serde_json::to_writer(&mut data, item)?;
+        data.push(b'\n');
+    }
+    Ok(data)
+}
+
+impl Inner {
+    fn append(&self, data: &[u8], count: usize, now: SystemTime) -> Result<()> {
+        let mut state = self.state.lock().expect("file lock poisoned");
+
+        let too_old = self.max_age.is_some_and(|max_age| {
+            now.duration_since(state.opened)
+                .is_ok_and(|age| age >= max_age)
+        });
+        if state.size > 0 && (state.size >= self.max_size || too_old) {
+            self.rotate(&mut state, now)?;
+        }
+
+        if state.file.is_none() {
+            self.open(&mut state)?;
+        }
+        if let Some(file) = state.file.as_mut() {
+            let mut writer = BufWriter::new(file);
+            writer.write_all(data)?;
+            writer.flush()?;
+        }
+        state.size += data.len() as u64;
+
+        debug!("wrote {} record(s) to {}", count, self.path.display());
+        Ok(())
+    }
+
+    fn open(&self, state: &mut State) -> Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("unable to open {}", self.path.display()))?;
+
+        // an existing file keeps its age across restarts
+        let metadata = file.metadata()?;
+        state.size = metadata.len();
+        state.opened = metadata
+            .created()
+            .ok()
+            .filter(|_| metadata.len() > 0)
+            .unwrap_or_else(SystemTime::now);
+
+        state.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&self, state: &mut State, now: SystemTime) -> Result<()> {
+        state.file = None;
+
+        let timestamp = DateTime::<Utc>::from(now).format(TIMESTAMP_FORMAT);
+        let mut rotated = self.sibling(&format!(".{}", timestamp));
+        let mut n = 1;
+        while rotated.exists() || gz(&rotated).exists() {
+            rotated = self.sibling(&format!(".{}.{}", timestamp, n));
+            n += 1;
+        }
+
+        fs::rename(&self.path, &rotated)
+            .with_context(|| format!("unable to rotate {}", self.path.display()))?;
+        info!("rotated {} to {}", self.path.display(), rotated.display());
+
+        if self.compress {
+            // a failed compression leaves the plain file in place
+            if let Err(e) = compress_file(&rotated) {
+                warn!("unable to compress {}. {:?}", rotated.display(), e);
+            }
+        }
+
+        self.open(state)?;
+        self.remove_expired();
+
+        Ok(())
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
+    // removes the oldest rotated files above the retention count
+    fn remove_expired(&self) {
+        let mut rotated = match self.rotated() {
+            Ok(rotated) => rotated,
+            Err(e) => {
+                warn!("unable to list rotated files. {:?}", e);
+                return;
+            }
+        };
+        if rotated.len() <= self.retention {
+            return;
+        }
+
+        rotated.sort();
+        let expired = rotated.len() - self.retention;
+        for (_, path) in rotated.into_iter().take(expired) {
+            debug!("removing rotated file {}", path.display());
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("unable to remove {}. {:?}", path.display(), e);
+            }
+        }
+    }
+
+    // only `<name>.<timestamp>[.<n>][.gz]`, other files next to it are kept
+    fn rotated(&self) -> Result<Vec<(SystemTime, PathBuf)>> {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            if is_rotated(&name, &entry.file_name().to_string_lossy()) {
+                rotated.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        Ok(rotated)
+    }
+}
+
+fn is_rotated(name: &str, candidate: &str) -> bool {
+    let suffix = match candidate
+        .strip_prefix(name)
+        .and_then(|rest| rest.strip_prefix('.'))
+    {
+        Some(suffix) => suffix.strip_suffix(".gz").unwrap_or(suffix),
+        None => return false,
+    };
+
+    let mut parts = suffix.splitn(2, '.');
+    let timestamp = parts.next().unwrap_or_default();
+    let valid_timestamp =
+        timestamp.len() == 16 && NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).is_ok();
+    let valid_counter = parts
+        .next()
+        .is_none_or(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()));
+
+    valid_timestamp && valid_counter
+}
+
+fn gz(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+// streams the file through the encoder, rotated files may be large
+fn compress_file(path: &Path) -> Result<()> {
+    let target = gz(path);
+
+    let tmp = target.with_extension("tmp");
+    let mut encoder = GzEncoder::new(BufWriter::new(File::create(&tmp)?), Level::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    fs::rename(&tmp, &target)?;
+    fs::remove_file(path)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileConfig {
+    path: PathBuf,
+    #[serde(default = "default_max_size")]
+    max_size: u64,
+    #[serde(default = "default_max_age")]
+    max_age: u64,
+    #[serde(default = "default_compress")]
+    compress: bool,
+    #[serde(default = "default_retention")]
+    retention: usize,
+}
+
+fn default_max_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_max_age() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_compress() -> bool {
+    true
+}
+
+fn default_retention() -> usize {
+    7
+}
+
+impl FileConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_size: default_max_size(),
+            max_age: default_max_age(),
+            compress: default_compress(),
+            retention: default_retention(),
+        }
+    }
+
+    /// Rotates after `max_size` bytes or `max_age` seconds, 0 disables age
+    /// based rotation.
+    pub fn with_rotation(mut self, max_size: u64, max_age: u64) -> Self {
+        self.max_size = max_size;
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn with_compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn with_retention(mut self, retention: usize) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn into_parts(self) -> (PathBuf, u64, Option<Duration>, bool, usize) {
+        let max_age = Some(self.max_age)
+            .filter(|max_age| *max_age > 0)
+            .map(Duration::from_secs);
+        (
+            self.path,
+            self.max_size,
+            max_age,
+            self.compress,
+            self.retention,
+        )
+    }
+}
+
+impl Sink for FileWriter {
+    fn send<'a>(&'a self, _: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        FileWriter::send(self, items).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn records(n: usize) -> Vec<Value> {
+        (0..n)
+            .map(|i| json!({"timestamp": "2020-05-01T10:00:01.000Z", "id": i, "name": "web"}))
+            .collect()
+    }
+
+    fn read_lines(data: &[u8]) -> Vec<Value> {
+        String::from_utf8(data.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    fn rotated(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name != "stats.jsonl")
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn it_appends_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out/stats.jsonl");
+
+        FileWriter::new(FileConfig::new(&path))
+            .unwrap()
+            .write(&records(2))
+            .unwrap();
+        FileWriter::new(FileConfig::new(&path))
+            .unwrap()
+            .write(&records(1))
+            .unwrap();
+
+        let lines = read_lines(&fs::read(&path).unwrap());
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], records(2)[1]);
+    }
+
+    #[test]
+    fn it_rotates_by_size_and_keeps_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.jsonl");
+        let config = FileConfig::new(&path)
+            .with_rotation(10, 0)
+            .with_retention(2);
+
+        let writer = FileWriter::new(config).unwrap();
+        for _ in 0..4 {
+            writer.write(&records(1)).unwrap();
+        }
+
+        let names = rotated(dir.path());
+        assert_eq!(names.len(), 2);
+        assert!(names.iter().all(|name| name.ends_with(".gz")));
+
+        for name in names {
+            let data = crate::mock::gunzip(&fs::read(dir.path().join(name)).unwrap());
+            assert_eq!(read_lines(&data), records(1));
+        }
+        assert_eq!(read_lines(&fs::read(&path).unwrap()), records(1));
+    }
+
+    #[tokio::test]
+    async fn it_keeps_unrelated_files_next_to_rotated_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.jsonl");
+        let unrelated = vec![
+            "stats.jsonl.bak",
+            "stats.jsonl.20200501T100001Z.tmp",
+            "stats.jsonl.2020-05-01",
+            "stats.jsonl.20200501T100001Z.1.old",
+            "stats.jsonl.old.gz",
+        ];
+        for name in &unrelated {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let config = FileConfig::new(&path)
+            .with_rotation(10, 0)
+            .with_retention(1);
+
+        let writer = FileWriter::new(config).unwrap();
+        for _ in 0..3 {
+            writer.send(&records(1)).await.unwrap();
+        }
+
+        let names = rotated(dir.path());
+        assert_eq!(names.len(), unrelated.len() + 1);
+        for name in &unrelated {
+            assert!(names.iter().any(|n| n == name));
+        }
+    }
+
+    #[test]
+    fn it_matches_rotated_file_names() {
+        assert!(is_rotated("stats.jsonl", "stats.jsonl.20200501T100001Z"));
+        assert!(is_rotated("stats.jsonl", "stats.jsonl.20200501T100001Z.gz"));
+        assert!(is_rotated("stats.jsonl", "stats.jsonl.20200501T100001Z.2"));
+        assert!(is_rotated(
+            "stats.jsonl",
+            "stats.jsonl.20200501T100001Z.2.gz"
+        ));
+        assert!(!is_rotated("stats.jsonl", "stats.jsonl"));
+        assert!(!is_rotated("stats.jsonl", "stats.jsonl.bak"));
+        assert!(!is_rotated(
+            "stats.jsonl",
+            "stats.jsonl.20200501T100001Z.gz.tmp"
+        ));
+        assert!(!is_rotated("stats.jsonl", "stats.jsonl.20200501T100001Z."));
+        assert!(!is_rotated("stats", "stats.jsonl.20200501T100001Z"));
+    }
+
+    #[test]
+    fn it_rotates_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.jsonl");
+        let config = FileConfig::new(&path)
+            .with_rotation(1024 * 1024, 60)
+            .with_compress(false);
+
+        let writer = FileWriter::new(config).unwrap();
+        let now = SystemTime::now();
+        writer.write_at(&records(1), now).unwrap();
+        writer
+            .write_at(&records(2), now + Duration::from_secs(30))
+            .unwrap();
+        assert!(rotated(dir.path()).is_empty());
+
+        writer
+            .write_at(&records(1), now + Duration::from_secs(61))
+            .unwrap();
+
+        let names = rotated(dir.path());
+        assert_eq!(names.len(), 1);
+        let data = fs::read(dir.path().join(&names[0])).unwrap();
+        assert_eq!(read_lines(&data).len(), 3);
+        assert_eq!(read_lines(&fs::read(&path).unwrap()), records(1));
+    }
+}
@@ -0,0 +1,415 @@
+use std::{collections::BTreeMap, fmt::Write, net::SocketAddr};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use futures_util::future::{BoxFuture, FutureExt};
+use hyper::{Body, Method, Request};
+use hyper_tls::HttpsConnector;
+use log::debug;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{net::UdpSocket, sync::Mutex};
+use url::Url;
+
+use crate::{
+    client::ResponseError,
+    compress::Compression,
+    packet,
+    proxy::{Proxy, ProxyConnector},
+    sink::Sink,
+};
+
+const MEASUREMENT: &str = "docker_container";
+
+/// Writes stats records in InfluxDB line protocol over HTTP or UDP.
+pub struct InfluxClient {
+    transport: Transport,
+}
+
+enum Transport {
+    Http {
+        url: String,
+        token: Option<String>,
+        compression: Compression,
+        // boxed to keep the variants of similar size
+        client: Box<hyper::Client<HttpsConnector<ProxyConnector>, Body>>,
+    },
+    Udp {
+        socket: Mutex<UdpSocket>,
+        addr: SocketAddr,
+        max_packet_size: usize,
+    },
+}
+
+impl InfluxClient {
+    pub fn new(config: InfluxConfig) -> Result<Self> {
+        let (url, org, bucket, token, compression, max_packet_size) = config.into_parts();
+        let parsed = Url::parse(&url)?;
+
+        let transport = match parsed.scheme() {
+            "http" | "https" => {
+                let bucket = bucket.ok_or_else(|| anyhow!("influx bucket is required"))?;
+                let mut write_url = parsed.join("api/v2/write")?;
+                write_url
+                    .query_pairs_mut()
+                    .append_pair("org", org.as_deref().unwrap_or_default())
+                    .append_pair("bucket", &bucket)
+                    .append_pair("precision", "ns");
+
+                let proxy = Proxy::for_url(None, write_url.as_str())?;
+                Transport::Http {
+                    url: write_url.to_string(),
+                    token,
+                    compression,
+                    client: Box::new(hyper::Client::builder().build(ProxyConnector::https(proxy)?)),
+                }
+            }
+            "udp" => {
+                let addr = parsed
+                    .socket_addrs(|| None)?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("unable to resolve {}", url))?;
+                let local: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = std::net::UdpSocket::bind(local)?;
+                socket.set_nonblocking(true)?;
+                let socket = UdpSocket::from_std(socket)?;
+
+                Transport::Udp {
+                    socket: Mutex::new(socket),
+                    addr,
+                    max_packet_size,
+                }
+            }
+            scheme => return Err(anyhow!("unsupported influx url scheme: {}", scheme)),
+        };
+
+        Ok(Self { transport })
+    }
+
+    pub async fn send(&self, items: &[Value]) -> Result<()> {
+        let lines = items.iter().filter_map(line).collect::<Vec<_>>();
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        match &self.transport {
+            Transport::Http {
+                url,
+                token,
+                compression,
+                client,
+            } => {
+                debug!("writing {} line(s) to {}", lines.len(), url);
+
+                let data = lines.concat();
+                let mut builder = Request::builder()
+                    .method(Method::POST)
+                    .uri(url.as_str())
+                    .header("Content-Type", "text/plain; charset=utf-8");
+                if let Some(token) = token {
+                    builder = builder.header("Authorization", format!("Token {}", token));
+                }
+                if let Some(encoding) = compression.content_encoding() {
+                    builder = builder.header("Content-Encoding", encoding);
+                }
+                let req = builder.body(Body::from(compression.encode(data.into_bytes())))?;
+
+                let res = client.request(req).await?;
+                if res.status().is_success() {
+                    return Ok(());
+                }
+
+                Err(ResponseError::from_response(res).await?.into())
+            }
+            Transport::Udp {
+                socket,
+                addr,
+                max_packet_size,
+            } => {
+                let packets = packet::pack(&lines, *max_packet_size);
+                debug!(
+                    "writing {} line(s) in {} packet(s)",
+                    lines.len(),
+                    packets.len()
+                );
+
+                let mut socket = socket.lock().await;
+                for packet in packets {
+                    socket
+                        .send_to(packet.as_bytes(), addr)
+                        .await
+                        .with_context(|| format!("unable to send data to {}", addr))?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Formats a serialized `Stats` record as a line. String properties and
+/// container labels become tags, numbers become fields. Returns `None` for
+/// records without a timestamp or fields.
+fn line(record: &Value) -> Option<String> {
+    let record = record.as_object()?;
+    let timestamp = nanos(record.get("timestamp")?.as_str()?)?;
+
+    let mut tags = BTreeMap::new();
+    let mut fields = Vec::new();
+    for (key, value) in record {
+        match value {
+            Value::String(_) if key == "timestamp" => {}
+            // a tag would add a series per aggregation window
+            Value::String(value) if key == "window_start" => {
+                if let Some(window_start) = nanos(value) {
+                    fields.push((key, format!("{}i", window_start)));
+                }
+            }
+            Value::String(value) => {
+                tags.insert(key.as_str(), value.as_str());
+            }
+            Value::Number(number) if number.is_f64() => fields.push((key, number.to_string())),
+            Value::Number(number) => fields.push((key, format!("{}i", number))),
+            _ => {}
+        }
+    }
+
+    // container labels must not override the properties of the container
+    if let Some(Value::Object(labels)) = record.get("labels") {
+        for (key, value) in labels {
+            if let Some(value) = value.as_str() {
+                tags.entry(key.as_str()).or_insert(value);
+            }
+        }
+    }
+
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut line = escape(MEASUREMENT, &[',', ' ']);
+    // tags are sorted by key as InfluxDB recommends
+    for (key, value) in tags.iter().filter(|(_, value)| !value.is_empty()) {
+        let _ = write!(
+            line,
+            ",{}={}",
+            escape(key, &[',', '=', ' ']),
+            escape(value, &[',', '=', ' '])
+        );
+    }
+
+    for (index, (key, value)) in fields.iter().enumerate() {
+        let separator = if index == 0 { ' ' } else { ',' };
+        let _ = write!(
+            line,
+            "{}{}={}",
+            separator,
+            escape(key, &[',', '=', ' ']),
+            value
+        );
+    }
+    let _ = writeln!(line, " {}", timestamp);
+
+    Some(line)
+}
+
+fn nanos(timestamp: &str) -> Option<i64> {
+    let timestamp = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    Some(timestamp.with_timezone(&Utc).timestamp_nanos())
+}
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            c if c == '\\' || special.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InfluxConfig {
+    url: String,
+    org: Option<String>,
+    bucket: Option<String>,
+    token: Option<String>,
+    #[serde(default)]
+    compression: Compression,
+    #[serde(default = "default_max_packet_size")]
+    max_packet_size: usize,
+}
+
+fn default_max_packet_size() -> usize {
+    1400
+}
+
+impl InfluxConfig {
+    /// Creates config for `http(s)://host:port` or `udp://host:port`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            org: None,
+            bucket: None,
+            token: None,
+            compression: Compression::default(),
+            max_packet_size: default_max_packet_size(),
+        }
+    }
+
+    pub fn with_bucket(mut self, org: impl Into<String>, bucket: impl Into<String>) -> Self {
+        self.org = Some(org.into());
+        self.bucket = Some(bucket.into());
+        self
+    }
+
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn into_parts(
+        self,
+    ) -> (
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        Compression,
+        usize,
+    ) {
+        (
+            self.url,
+            self.org,
+            self.bucket,
+            self.token,
+            self.compression,
+            self.max_packet_size,
+        )
+    }
+}
+
+impl Sink for InfluxClient {
+    fn send<'a>(&'a self, _: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        InfluxClient::send(self, items).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Response, StatusCode};
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{self, RecordingServer};
+
+    fn record(id: &str) -> Value {
+        json!({
+            "timestamp": "2020-05-01T10:00:01.123Z",
+            "id": id,
+            "name": "web 1",
+            "cpu_percentage": 20.5,
+            "memory": 1024,
+            "memory_percentage": null,
+            "image": "nginx:1.17",
+            "labels": { "tier": "front,end", "name": "ignored" },
+        })
+    }
+
+    const LINE: &str = "docker_container,id=0123456789ab,image=nginx:1.17,name=web\\ 1,tier=front\\,end cpu_percentage=20.5,memory=1024i 1588327201123000000\n";
+
+    #[test]
+    fn it_formats_line_protocol() {
+        assert_eq!(line(&record("0123456789ab")).as_deref(), Some(LINE));
+        assert_eq!(
+            line(&json!({"timestamp": "2020-05-01T10:00:01Z", "id": "a"})),
+            None
+        );
+    }
+
+    #[test]
+    fn it_writes_aggregation_window_as_field() {
+        let mut record = record("0123456789ab");
+        record["window_start"] = json!("2020-05-01T09:59:01.123Z");
+        record["samples"] = json!(6);
+
+        assert_eq!(
+            line(&record).as_deref(),
+            Some("docker_container,id=0123456789ab,image=nginx:1.17,name=web\\ 1,tier=front\\,end cpu_percentage=20.5,memory=1024i,samples=6i,window_start=1588327141123000000i 1588327201123000000\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn it_writes_gzipped_lines_over_http() {
+        let server = RecordingServer::start(|_, _| {
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = StatusCode::NO_CONTENT;
+            res
+        });
+
+        let config = InfluxConfig::new(server.url())
+            .with_bucket("docmon", "stats")
+            .with_token("secret")
+            .with_compression(Compression::Gzip);
+        let client = InfluxClient::new(config).unwrap();
+        client
+            .send(&[record("0123456789ab"), record("ba9876543210")])
+            .await
+            .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(
+            request.path,
+            "/api/v2/write?org=docmon&bucket=stats&precision=ns"
+        );
+        assert_eq!(request.headers["Authorization"], "Token secret");
+        assert_eq!(request.headers["Content-Encoding"], "gzip");
+
+        let body = String::from_utf8(mock::gunzip(&request.body)).unwrap();
+        assert_eq!(body.lines().count(), 2);
+        assert!(body.starts_with(LINE));
+    }
+
+    #[tokio::test]
+    async fn it_writes_lines_over_udp() {
+        let mut server = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let url = format!("udp://{}", server.local_addr().unwrap());
+
+        let client = InfluxClient::new(InfluxConfig::new(url).with_max_packet_size(200)).unwrap();
+        client
+            .send(&[record("0123456789ab"), record("ba9876543210")])
+            .await
+            .unwrap();
+
+        let mut buf = [0; 1500];
+        let (len, _) = server.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], LINE.as_bytes());
+        let (len, _) = server.recv_from(&mut buf).await.unwrap();
+        assert!(std::str::from_utf8(&buf[..len])
+            .unwrap()
+            .contains("id=ba9876543210"));
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::Result;
+use futures_util::future::{BoxFuture, FutureExt};
+use hyper::{Body, Method, Request, StatusCode};
+use hyper_tls::HttpsConnector;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    client::ResponseError,
+    compress::Compression,
+    proxy::{Proxy, ProxyConnector},
+    sink::Sink,
+    token::{CredentialConfig, TokenProvider},
+};
+
+const API_VERSION: &str = "2023-01-01";
+const SCOPE: &str = "https://monitor.azure.com//.default";
+
+/// Sends records to the Azure Monitor Logs Ingestion API through a data
+/// collection endpoint and a data collection rule (DCR).
+pub struct IngestionClient {
+    url: String,
+    token: TokenProvider,
+    compression: Compression,
+    client: hyper::Client<HttpsConnector<ProxyConnector>, Body>,
+}
+
+impl IngestionClient {
+    pub fn new(config: IngestionConfig) -> Result<Self> {
+        let (endpoint, dcr_immutable_id, credential, compression) = config.into_parts();
+
+        let url = format!(
+            "{}/dataCollectionRules/{}/streams",
+            endpoint.trim_end_matches('/'),
+            dcr_immutable_id
+        );
+
+        let proxy = Proxy::for_url(None, &url)?;
+        let client = hyper::Client::builder().build(ProxyConnector::https(proxy)?);
+        let token = TokenProvider::new(credential, SCOPE)?;
+
+        Ok(Self {
+            url,
+            token,
+            compression,
+            client,
+        })
+    }
+
+    /// Uploads `items` to the DCR stream named `stream`, e.g. `Custom-StatEntries`.
+    pub async fn send<I>(&self, stream: &str, items: &I) -> Result<()>
+    where
+        I: Serialize + ?Sized,
+    {
+        let data = self.compression.encode(serde_json::to_vec(items)?);
+        let url = format!("{}/{}?api-version={}", self.url, stream, API_VERSION);
+
+        debug!("sending data to {} stream", stream);
+
+        let mut token_refreshed = false;
+        loop {
+            let token = self.token.token().await?;
+            let mut builder = Request::builder();
+            if let Some(encoding) = self.compression.content_encoding() {
+                builder = builder.header("Content-Encoding", encoding);
+            }
+            let req = builder
+                .method(Method::POST)
+                .uri(&url)
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::from(data.clone()))?;
+
+            let res = self.client.request(req).await?;
+            let status = res.status();
+
+            if status.is_success() {
+                return Ok(());
+            }
+
+            // the token could have been revoked before it expired
+            if status == StatusCode::UNAUTHORIZED && !token_refreshed {
+                warn!("access token rejected, requesting a new one");
+                self.token.invalidate().await;
+                token_refreshed = true;
+                continue;
+            }
+
+            return Err(ResponseError::from_response(res).await?.into());
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IngestionConfig {
+    endpoint: String,
+    dcr_immutable_id: String,
+    #[serde(flatten)]
+    credential: CredentialConfig,
+    #[serde(default)]
+    compression: Compression,
+}
+
+impl IngestionConfig {
+    pub fn new(
+        endpoint: impl Into<String>,
+        dcr_immutable_id: impl Into<String>,
+        credential: CredentialConfig,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            dcr_immutable_id: dcr_immutable_id.into(),
+            credential,
+            compression: Compression::default(),
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn into_parts(self) -> (String, String, CredentialConfig, Compression) {
+        (
+            self.endpoint,
+            self.dcr_immutable_id,
+            self.credential,
+            self.compression,
+        )
+    }
+}
+
+impl Sink for IngestionClient {
+    fn send<'a>(&'a self, log_name: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        IngestionClient::send(self, log_name, items).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Response;
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::mock::{Recorded, RecordingServer};
+
+    fn is_token_request(request: &Recorded) -> bool {
+        request.path.contains("oauth2")
+    }
+
+    // issues a new token per request and rejects the first upload as unauthorized
+    fn ingestion_server() -> RecordingServer {
+        RecordingServer::start(|request, earlier| {
+            if is_token_request(request) {
+                let tokens = earlier.iter().filter(|r| is_token_request(r)).count();
+                let body = json!({
+                    "expires_in": 3600,
+                    "access_token": format!("token-{}", tokens),
+                });
+                return Response::new(Body::from(body.to_string()));
+            }
+
+            let mut res = Response::new(Body::empty());
+            *res.status_mut() = if request.headers["Authorization"] == "Bearer token-0" {
+                StatusCode::UNAUTHORIZED
+            } else {
+                StatusCode::NO_CONTENT
+            };
+            res
+        })
+    }
+
+    #[tokio::test]
+    async fn it_sends_data_to_dcr_stream() {
+        let server = ingestion_server();
+        let credential = CredentialConfig::with_secret("tenant", "client", "secret")
+            .with_authority(server.url());
+        let config = IngestionConfig::new(server.url(), "dcr-1", credential);
+        let client = IngestionClient::new(config).unwrap();
+
+        let items = vec![json!({"id": "0123456789ab", "cpu_percentage": 1.5})];
+        client.send("Custom-StatEntries", &items).await.unwrap();
+
+        let uploads = server
+            .requests()
+            .into_iter()
+            .filter(|request| !is_token_request(request))
+            .collect::<Vec<_>>();
+        assert_eq!(uploads.len(), 2);
+
+        let upload = &uploads[1];
+        assert_eq!(
+            upload.path,
+            "/dataCollectionRules/dcr-1/streams/Custom-StatEntries?api-version=2023-01-01"
+        );
+        assert_eq!(upload.headers["Authorization"], "Bearer token-1");
+        let body: Value = serde_json::from_slice(&upload.body).unwrap();
+        assert_eq!(body, Value::from(items));
+    }
+}
@@ -1,9 +1,45 @@
+mod backoff;
+mod channel;
 mod client;
+mod compress;
 mod config;
+mod file;
+mod influx;
+mod ingestion;
+#[cfg(test)]
+mod mock;
+mod otlp;
+mod output;
+mod packet;
+mod prometheus;
+mod proxy;
 mod publish;
+mod retry;
+mod sink;
+mod spool;
 mod stats;
+mod statsd;
+mod stdout;
+mod token;
 
 pub use crate::config::Config;
-pub use client::{Client, ClientConfig};
+pub use channel::{ChannelConfig, Overflow};
+pub use client::{Client, ClientConfig, Cloud, ResponseError};
+pub use compress::Compression;
+pub use file::{FileConfig, FileWriter};
+pub use influx::{InfluxClient, InfluxConfig};
+pub use ingestion::{IngestionClient, IngestionConfig};
+pub use otlp::{OtlpConfig, OtlpExporter, OtlpProtocol};
+pub use output::{OutputConfig, SinkConfig};
+pub use prometheus::{PrometheusConfig, PrometheusExporter};
 pub use publish::{Publisher, PublisherConfig, PublisherHandle};
-pub use stats::{Collector, Stats};
+pub use retry::RetryConfig;
+pub use sink::{Limits, MemorySink, Sink};
+pub use spool::SpoolConfig;
+pub use stats::{
+    Aggregate, Collector, CollectorConfig, FilterConfig, Metadata, MetadataConfig, Router,
+    RulesConfig, SamplingConfig, Stats,
+};
+pub use statsd::{StatsdClient, StatsdConfig};
+pub use stdout::{StdoutConfig, StdoutWriter};
+pub use token::CredentialConfig;
@@ -0,0 +1,402 @@
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    error::Error,
+    io::{self, Read},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use hyper::{
+    body::{self, HttpBody},
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Request, Response, Server, StatusCode, Uri, Version,
+};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time,
+};
+
+use crate::ClientConfig;
+
+pub const CUSTOMER_ID: &str = "00000000-0000-0000-0000-000000000000";
+pub const SHARED_KEY: &str = "bW9jay1zaGFyZWQta2V5";
+
+/// A scripted response of the mock server. Requests are answered with `200 OK`
+/// once the script is exhausted.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    Status(StatusCode),
+    RetryAfter(StatusCode, u64),
+    Delay(Duration),
+}
+
+/// A request received by the mock server.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub log_type: String,
+    pub content_encoding: Option<String>,
+    pub status: StatusCode,
+    pub payload: Value,
+}
+
+#[derive(Default)]
+struct State {
+    script: VecDeque<Reply>,
+    received: Vec<Received>,
+}
+
+/// In-process HTTP Data Collector API which checks requests the same way
+/// the real service does and records their payloads.
+pub struct MockServer {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockServer {
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let shared = state.clone();
+        let make_svc = make_service_fn(move |_| {
+            let state = shared.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        Self { url, state }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig::new(CUSTOMER_ID, SHARED_KEY).with_endpoint(&self.url)
+    }
+
+    /// Answers next requests with `replies` in order.
+    pub fn script(&self, replies: impl IntoIterator<Item = Reply>) {
+        self.state.lock().unwrap().script.extend(replies);
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Returns payloads of successful requests.
+    pub fn accepted(&self) -> Vec<Value> {
+        self.received()
+            .into_iter()
+            .filter(|received| received.status == StatusCode::OK)
+            .map(|received| received.payload)
+            .collect()
+    }
+}
+
+/// A request recorded by `RecordingServer`, with the path including the
+/// query and the body as sent.
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub path: String,
+    pub version: Version,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+type Responder<B> = dyn Fn(&Recorded, &[Recorded]) -> Response<B> + Send + Sync;
+
+/// In-process HTTP server which records every request and answers it with
+/// `respond`, called with the request and the ones recorded before it.
+pub struct RecordingServer {
+    url: String,
+    requests: Arc<Mutex<Vec<Recorded>>>,
+}
+
+impl RecordingServer {
+    pub fn start<F, B>(respond: F) -> Self
+    where
+        F: Fn(&Recorded, &[Recorded]) -> Response<B> + Send + Sync + 'static,
+        B: HttpBody + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let respond: Arc<Responder<B>> = Arc::new(respond);
+
+        let shared = requests.clone();
+        let make_svc = make_service_fn(move |_| {
+            let requests = shared.clone();
+            let respond = respond.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    record(requests.clone(), respond.clone(), req)
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let url = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        Self { url, requests }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+async fn record<B>(
+    requests: Arc<Mutex<Vec<Recorded>>>,
+    respond: Arc<Responder<B>>,
+    req: Request<Body>,
+) -> Result<Response<B>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let body = body::to_bytes(body).await.unwrap_or_default();
+    let request = Recorded {
+        path: parts
+            .uri
+            .path_and_query()
+            .map_or("/", |path| path.as_str())
+            .to_string(),
+        version: parts.version,
+        headers: parts.headers,
+        body: body.to_vec(),
+    };
+
+    let mut requests = requests.lock().unwrap();
+    let res = respond(&request, &requests);
+    requests.push(request);
+
+    Ok(res)
+}
+
+/// In-process HTTP proxy which tunnels CONNECT requests, forwards plain
+/// requests in origin form and records the request heads it receives. Only
+/// the first request of a connection is inspected.
+pub struct MockProxy {
+    url: String,
+    heads: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockProxy {
+    pub fn start() -> Self {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut listener = TcpListener::from_std(listener).unwrap();
+
+        let heads = Arc::new(Mutex::new(Vec::new()));
+        let shared = heads.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(forward(stream, shared.clone()));
+            }
+        });
+
+        Self { url, heads }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn heads(&self) -> Vec<String> {
+        self.heads.lock().unwrap().clone()
+    }
+}
+
+async fn forward(mut client: TcpStream, heads: Arc<Mutex<Vec<String>>>) -> io::Result<()> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(client.read_u8().await?);
+    }
+    let head = String::from_utf8_lossy(&head).into_owned();
+    heads.lock().unwrap().push(head.clone());
+
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+
+    let mut server = if method == "CONNECT" {
+        let addr = target.parse::<SocketAddr>().map_err(invalid)?;
+        let server = TcpStream::connect(addr).await?;
+        client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await?;
+        server
+    } else {
+        let uri = target.parse::<Uri>().map_err(invalid)?;
+        let addr = uri
+            .authority()
+            .map_or("", |authority| authority.as_str())
+            .parse::<SocketAddr>()
+            .map_err(invalid)?;
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        let mut server = TcpStream::connect(addr).await?;
+        let rest = &head[request_line.len()..];
+        server
+            .write_all(format!("{} {} HTTP/1.1{}", method, path, rest).as_bytes())
+            .await?;
+        server
+    };
+
+    let (mut client_read, mut client_write) = client.split();
+    let (mut server_read, mut server_write) = server.split();
+    futures_util::future::try_join(
+        tokio::io::copy(&mut client_read, &mut server_write),
+        tokio::io::copy(&mut server_read, &mut client_write),
+    )
+    .await?;
+
+    Ok(())
+}
+
+fn invalid<E>(_: E) -> io::Error {
+    io::ErrorKind::InvalidInput.into()
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let body = body::to_bytes(body).await.unwrap_or_default();
+    let content_encoding = header(&parts.headers, "Content-Encoding");
+    let payload = decode(content_encoding.as_deref(), &body);
+
+    let log_type = header(&parts.headers, "Log-Type").unwrap_or_default();
+    // scripted replies are used by valid requests only
+    let validated = validate(&parts.uri.to_string(), &parts.headers, body.len(), &payload);
+    let (status, retry_after) = match validated {
+        Err(status) => (status, None),
+        Ok(()) => {
+            let reply = state.lock().unwrap().script.pop_front();
+            match reply {
+                Some(Reply::Delay(delay)) => {
+                    time::delay_for(delay).await;
+                    (StatusCode::OK, None)
+                }
+                Some(Reply::Status(status)) => (status, None),
+                Some(Reply::RetryAfter(status, seconds)) => (status, Some(seconds)),
+                None => (StatusCode::OK, None),
+            }
+        }
+    };
+
+    state.lock().unwrap().received.push(Received {
+        log_type,
+        content_encoding,
+        status,
+        payload: serde_json::from_slice(&payload).unwrap_or(Value::Null),
+    });
+
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = status;
+    if let Some(seconds) = retry_after {
+        res.headers_mut()
+            .insert("Retry-After", seconds.to_string().parse().unwrap());
+    }
+
+    Ok(res)
+}
+
+// the signature covers `content_length` of the body as sent, `payload` is
+// the decompressed body
+fn validate(
+    uri: &str,
+    headers: &HeaderMap,
+    content_length: usize,
+    payload: &[u8],
+) -> Result<(), StatusCode> {
+    if uri != "/api/logs?api-version=2016-04-01" {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let log_type = header(headers, "Log-Type").ok_or(StatusCode::BAD_REQUEST)?;
+    let valid_log_type = !log_type.is_empty()
+        && log_type.len() <= 100
+        && log_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_log_type {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    if header(headers, "time-generated-field").as_deref() != Some("timestamp")
+        || header(headers, "Content-Type").as_deref() != Some("application/json")
+        || serde_json::from_slice::<Vec<Value>>(payload).is_err()
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let date = header(headers, "x-ms-date").ok_or(StatusCode::FORBIDDEN)?;
+    let sent = DateTime::parse_from_rfc2822(&date).map_err(|_| StatusCode::FORBIDDEN)?;
+    if (Utc::now() - sent.with_timezone(&Utc)).num_minutes().abs() > 15 {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let expected = format!("SharedKey {}:{}", CUSTOMER_ID, sign(&date, content_length));
+    if header(headers, "Authorization").as_deref() != Some(expected.as_str()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(())
+}
+
+fn sign(date: &str, content_length: usize) -> String {
+    let secret = format!(
+        "POST\n{}\napplication/json\nx-ms-date:{}\n/api/logs",
+        content_length, date
+    );
+    let key = PKey::hmac(&base64::decode(SHARED_KEY).unwrap()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(secret.as_bytes()).unwrap();
+
+    base64::encode(signer.sign_to_vec().unwrap())
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Decompresses a gzip stream with flate2 as the reference decoder.
+pub fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    flate2::read::GzDecoder::new(data)
+        .read_to_end(&mut out)
+        .expect("gzip stream");
+    out
+}
+
+/// Decompresses a request body according to `Content-Encoding`.
+pub fn decode(content_encoding: Option<&str>, data: &[u8]) -> Vec<u8> {
+    match content_encoding {
+        None => data.to_vec(),
+        Some("gzip") => gunzip(data),
+        Some("deflate") => {
+            let mut out = Vec::new();
+            flate2::read::ZlibDecoder::new(data)
+                .read_to_end(&mut out)
+                .expect("zlib stream");
+            out
+        }
+        Some(encoding) => panic!("unexpected content encoding {}", encoding),
+    }
+}
@@ -0,0 +1,561 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+use super::proto::Message;
+
+const SCOPE_NAME: &str = "docmon";
+// OTLP AggregationTemporality
+const CUMULATIVE: u64 = 2;
+// containers not seen for an hour are forgotten, in nanoseconds
+const STALE_AFTER: u64 = 3600 * 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Gauge,
+    MonotonicSum,
+}
+
+/// How stats record fields map to an OTLP metric. Every field produces a
+/// data point with an optional attribute, e.g. the direction of network IO.
+#[derive(Debug)]
+struct Instrument {
+    name: &'static str,
+    description: &'static str,
+    unit: &'static str,
+    kind: Kind,
+    fields: &'static [(&'static str, Option<(&'static str, &'static str)>)],
+}
+
+const INSTRUMENTS: &[Instrument] = &[
+    Instrument {
+        name: "container.cpu.utilization",
+        description: "CPU usage in percent, 100 per fully used CPU.",
+        unit: "%",
+        kind: Kind::Gauge,
+        fields: &[("cpu_percentage", None)],
+    },
+    Instrument {
+        name: "container.memory.usage",
+        description: "Memory usage excluding page cache.",
+        unit: "By",
+        kind: Kind::Gauge,
+        fields: &[("memory", None)],
+    },
+    Instrument {
+        name: "container.memory.utilization",
+        description: "Memory usage in percent of the memory limit.",
+        unit: "%",
+        kind: Kind::Gauge,
+        fields: &[("memory_percentage", None)],
+    },
+    Instrument {
+        name: "container.memory.limit",
+        description: "Memory limit.",
+        unit: "By",
+        kind: Kind::Gauge,
+        fields: &[("memory_limit", None)],
+    },
+    Instrument {
+        name: "container.network.io",
+        description: "Bytes received and sent over all network interfaces.",
+        unit: "By",
+        kind: Kind::MonotonicSum,
+        fields: &[
+            ("network_rx", Some(("network.io.direction", "receive"))),
+            ("network_tx", Some(("network.io.direction", "transmit"))),
+        ],
+    },
+    Instrument {
+        name: "container.disk.io",
+        description: "Bytes read from and written to block devices.",
+        unit: "By",
+        kind: Kind::MonotonicSum,
+        fields: &[
+            ("block_read", Some(("disk.io.direction", "read"))),
+            ("block_write", Some(("disk.io.direction", "write"))),
+        ],
+    },
+    Instrument {
+        name: "container.pids.count",
+        description: "Number of processes and threads.",
+        unit: "{process}",
+        kind: Kind::Gauge,
+        fields: &[("pid", None)],
+    },
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum AnyValue {
+    String(String),
+    Array(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Attribute {
+    key: &'static str,
+    value: AnyValue,
+}
+
+impl Attribute {
+    fn string(key: &'static str, value: impl Into<String>) -> Self {
+        Self {
+            key,
+            value: AnyValue::String(value.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Double(f64),
+    Int(i64),
+}
+
+#[derive(Debug)]
+struct Point {
+    attributes: Vec<Attribute>,
+    start_time: Option<u64>,
+    time: u64,
+    value: Number,
+}
+
+#[derive(Debug)]
+struct Metric {
+    instrument: &'static Instrument,
+    points: Vec<Point>,
+}
+
+#[derive(Debug)]
+struct ResourceMetrics {
+    container_id: String,
+    attributes: Vec<Attribute>,
+    metrics: Vec<Metric>,
+}
+
+/// The first observation of every container, which is the start time of its
+/// cumulative sums as stats records don't tell when a container started.
+#[derive(Debug, Default)]
+pub struct StartTimes(HashMap<String, (u64, u64)>);
+
+impl StartTimes {
+    // returns the start time of the container observed at `time`
+    fn observe(&mut self, id: &str, time: u64) -> u64 {
+        let (start, last) = self.0.entry(id.to_string()).or_insert((time, time));
+        *start = (*start).min(time);
+        *last = (*last).max(time);
+        *start
+    }
+
+    fn forget_stale(&mut self) {
+        if let Some(newest) = self.0.values().map(|(_, last)| *last).max() {
+            self.0
+                .retain(|_, (_, last)| newest.saturating_sub(*last) < STALE_AFTER);
+        }
+    }
+}
+
+/// An OTLP `ExportMetricsServiceRequest` with one resource per container.
+#[derive(Debug)]
+pub struct ExportRequest(Vec<ResourceMetrics>);
+
+impl ExportRequest {
+    /// Converts serialized `Stats` records. Records without an id or a valid
+    /// timestamp are skipped.
+    pub fn from_records(records: &[Value], start_times: &mut StartTimes) -> Self {
+        let mut resources: Vec<ResourceMetrics> = Vec::new();
+
+        for record in records {
+            let (id, time) = match (record["id"].as_str(), timestamp(record)) {
+                (Some(id), Some(time)) => (id, time),
+                _ => continue,
+            };
+            let start_time = start_times.observe(id, time);
+
+            let index = match resources.iter().position(|r| r.container_id == id) {
+                Some(index) => index,
+                None => {
+                    resources.push(ResourceMetrics {
+                        container_id: id.to_string(),
+                        attributes: resource(record),
+                        metrics: Vec::new(),
+                    });
+                    resources.len() - 1
+                }
+            };
+            let resource = &mut resources[index];
+
+            for instrument in INSTRUMENTS {
+                for (field, attribute) in instrument.fields {
+                    let value = match number(&record[*field]) {
+                        Some(value) => value,
+                        None => continue,
+                    };
+
+                    let point = Point {
+                        attributes: attribute
+                            .iter()
+                            .map(|(key, value)| Attribute::string(key, *value))
+                            .collect(),
+                        start_time: match instrument.kind {
+                            Kind::Gauge => None,
+                            Kind::MonotonicSum => Some(start_time),
+                        },
+                        time,
+                        value,
+                    };
+
+                    match resource
+                        .metrics
+                        .iter_mut()
+                        .find(|metric| metric.instrument.name == instrument.name)
+                    {
+                        Some(metric) => metric.points.push(point),
+                        None => resource.metrics.push(Metric {
+                            instrument,
+                            points: vec![point],
+                        }),
+                    }
+                }
+            }
+        }
+        start_times.forget_stale();
+
+        Self(resources)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Encodes the request in protobuf wire format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut request = Message::new();
+
+        for resource in &self.0 {
+            let mut attributes = Message::new();
+            for attribute in &resource.attributes {
+                attributes.message(1, &encode_attribute(attribute));
+            }
+
+            let mut scope = Message::new();
+            scope
+                .string(1, SCOPE_NAME)
+                .string(2, env!("CARGO_PKG_VERSION"));
+
+            let mut scope_metrics = Message::new();
+            scope_metrics.message(1, &scope);
+            for metric in &resource.metrics {
+                scope_metrics.message(2, &encode_metric(metric));
+            }
+
+            let mut resource_metrics = Message::new();
+            resource_metrics
+                .message(1, &attributes)
+                .message(2, &scope_metrics);
+
+            request.message(1, &resource_metrics);
+        }
+
+        request.into_bytes()
+    }
+
+    /// Encodes the request in OTLP/JSON format.
+    pub fn to_json(&self) -> Value {
+        let resource_metrics = self
+            .0
+            .iter()
+            .map(|resource| {
+                json!({
+                    "resource": {
+                        "attributes": resource.attributes.iter().map(attribute_json).collect::<Vec<_>>(),
+                    },
+                    "scopeMetrics": [{
+                        "scope": { "name": SCOPE_NAME, "version": env!("CARGO_PKG_VERSION") },
+                        "metrics": resource.metrics.iter().map(metric_json).collect::<Vec<_>>(),
+                    }],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "resourceMetrics": resource_metrics })
+    }
+}
+
+fn timestamp(record: &Value) -> Option<u64> {
+    let timestamp = record["timestamp"].as_str()?;
+    let timestamp = DateTime::parse_from_rfc3339(timestamp).ok()?;
+    let nanos = timestamp.with_timezone(&Utc).timestamp_nanos();
+
+    Some(nanos as u64)
+}
+
+fn number(value: &Value) -> Option<Number> {
+    match value {
+        Value::Number(number) if number.is_f64() => number.as_f64().map(Number::Double),
+        Value::Number(number) => number.as_i64().map(Number::Int),
+        _ => None,
+    }
+}
+
+// follows the OpenTelemetry semantic conventions for containers and hosts
+fn resource(record: &Value) -> Vec<Attribute> {
+    let mut attributes = vec![
+        Attribute::string("container.id", record["id"].as_str().unwrap_or_default()),
+        Attribute::string("container.runtime", "docker"),
+    ];
+
+    if let Some(name) = record["name"].as_str() {
+        attributes.push(Attribute::string("container.name", name));
+    }
+
+    if let Some(image) = record["image"].as_str() {
+        let (name, tag) = split_image(image);
+        attributes.push(Attribute::string("container.image.name", name));
+        if let Some(tag) = tag {
+            attributes.push(Attribute {
+                key: "container.image.tags",
+                value: AnyValue::Array(vec![tag.to_string()]),
+            });
+        }
+    }
+
+    if let Some(host_name) = record["host_name"].as_str() {
+        attributes.push(Attribute::string("host.name", host_name));
+    }
+
+    attributes
+}
+
+// `registry:5000/app:1.0@sha256:...` is `registry:5000/app` tagged `1.0`
+fn split_image(image: &str) -> (&str, Option<&str>) {
+    let image = image.split('@').next().unwrap_or(image);
+    match image.rfind(':') {
+        Some(index) if !image[index..].contains('/') => {
+            (&image[..index], Some(&image[index + 1..]))
+        }
+        _ => (image, None),
+    }
+}
+
+fn encode_attribute(attribute: &Attribute) -> Message {
+    let mut value = Message::new();
+    match &attribute.value {
+        AnyValue::String(string) => {
+            value.bytes(1, string.as_bytes());
+        }
+        AnyValue::Array(strings) => {
+            let mut array = Message::new();
+            for string in strings {
+                let mut item = Message::new();
+                item.bytes(1, string.as_bytes());
+                array.message(1, &item);
+            }
+            value.message(5, &array);
+        }
+    }
+
+    let mut key_value = Message::new();
+    key_value.string(1, attribute.key).message(2, &value);
+    key_value
+}
+
+fn encode_metric(metric: &Metric) -> Message {
+    let instrument = metric.instrument;
+
+    let mut data = Message::new();
+    for point in &metric.points {
+        let mut data_point = Message::new();
+        if let Some(start_time) = point.start_time {
+            data_point.fixed64(2, start_time);
+        }
+        data_point.fixed64(3, point.time);
+        match point.value {
+            Number::Double(value) => data_point.double(4, value),
+            Number::Int(value) => data_point.sfixed64(6, value),
+        };
+        for attribute in &point.attributes {
+            data_point.message(7, &encode_attribute(attribute));
+        }
+        data.message(1, &data_point);
+    }
+
+    let mut message = Message::new();
+    message
+        .string(1, instrument.name)
+        .string(2, instrument.description)
+        .string(3, instrument.unit);
+
+    match instrument.kind {
+        Kind::Gauge => message.message(5, &data),
+        Kind::MonotonicSum => {
+            data.varint(2, CUMULATIVE).bool(3, true);
+            message.message(7, &data)
+        }
+    };
+
+    message
+}
+
+fn attribute_json(attribute: &Attribute) -> Value {
+    let value = match &attribute.value {
+        AnyValue::String(string) => json!({ "stringValue": string }),
+        AnyValue::Array(strings) => json!({
+            "arrayValue": {
+                "values": strings.iter().map(|string| json!({ "stringValue": string })).collect::<Vec<_>>(),
+            }
+        }),
+    };
+
+    json!({ "key": attribute.key, "value": value })
+}
+
+// 64-bit integers are strings in the protobuf JSON mapping
+fn metric_json(metric: &Metric) -> Value {
+    let instrument = metric.instrument;
+    let points = metric
+        .points
+        .iter()
+        .map(|point| {
+            let mut data_point = json!({
+                "timeUnixNano": point.time.to_string(),
+                "attributes": point.attributes.iter().map(attribute_json).collect::<Vec<_>>(),
+            });
+            if let Some(start_time) = point.start_time {
+                data_point["startTimeUnixNano"] = json!(start_time.to_string());
+            }
+            match point.value {
+                Number::Double(value) => data_point["asDouble"] = json!(value),
+                Number::Int(value) => data_point["asInt"] = json!(value.to_string()),
+            }
+            data_point
+        })
+        .collect::<Vec<_>>();
+
+    let mut metric = json!({
+        "name": instrument.name,
+        "description": instrument.description,
+        "unit": instrument.unit,
+    });
+    match instrument.kind {
+        Kind::Gauge => metric["gauge"] = json!({ "dataPoints": points }),
+        Kind::MonotonicSum => {
+            metric["sum"] = json!({
+                "dataPoints": points,
+                "aggregationTemporality": CUMULATIVE,
+                "isMonotonic": true,
+            })
+        }
+    }
+
+    metric
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(second: u32, network_rx: u64) -> Value {
+        json!({
+            "timestamp": format!("2020-05-01T10:00:{:02}.000Z", second),
+            "id": "0123456789ab",
+            "name": "web",
+            "cpu_percentage": 20.0,
+            "memory": 1024,
+            "memory_percentage": null,
+            "network_rx": network_rx,
+            "network_tx": 256,
+            "image": "nginx:1.17",
+            "host_name": "docker-host",
+        })
+    }
+
+    #[test]
+    fn it_splits_image_references() {
+        assert_eq!(split_image("nginx:1.17"), ("nginx", Some("1.17")));
+        assert_eq!(split_image("nginx"), ("nginx", None));
+        assert_eq!(
+            split_image("registry:5000/app:1.0@sha256:abc"),
+            ("registry:5000/app", Some("1.0"))
+        );
+        assert_eq!(
+            split_image("registry:5000/app"),
+            ("registry:5000/app", None)
+        );
+    }
+
+    #[test]
+    fn it_groups_records_by_container() {
+        let mut start_times = StartTimes::default();
+        let request =
+            ExportRequest::from_records(&[record(1, 512), record(2, 1024)], &mut start_times);
+        let json = request.to_json();
+
+        let resources = json["resourceMetrics"].as_array().unwrap();
+        assert_eq!(resources.len(), 1);
+        assert_eq!(
+            resources[0]["resource"]["attributes"],
+            json!([
+                { "key": "container.id", "value": { "stringValue": "0123456789ab" } },
+                { "key": "container.runtime", "value": { "stringValue": "docker" } },
+                { "key": "container.name", "value": { "stringValue": "web" } },
+                { "key": "container.image.name", "value": { "stringValue": "nginx" } },
+                { "key": "container.image.tags", "value": { "arrayValue": { "values": [{ "stringValue": "1.17" }] } } },
+                { "key": "host.name", "value": { "stringValue": "docker-host" } },
+            ])
+        );
+
+        let metrics = resources[0]["scopeMetrics"][0]["metrics"]
+            .as_array()
+            .unwrap();
+        let names = metrics
+            .iter()
+            .map(|metric| metric["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "container.cpu.utilization",
+                "container.memory.usage",
+                "container.network.io"
+            ]
+        );
+
+        assert_eq!(
+            metrics[0]["gauge"]["dataPoints"][1],
+            json!({ "timeUnixNano": "1588327202000000000", "attributes": [], "asDouble": 20.0 })
+        );
+
+        let sum = &metrics[2]["sum"];
+        assert_eq!(sum["isMonotonic"], true);
+        assert_eq!(sum["aggregationTemporality"], 2);
+        assert_eq!(sum["dataPoints"].as_array().unwrap().len(), 4);
+        assert_eq!(
+            sum["dataPoints"][2],
+            json!({
+                "startTimeUnixNano": "1588327201000000000",
+                "timeUnixNano": "1588327202000000000",
+                "attributes": [{ "key": "network.io.direction", "value": { "stringValue": "receive" } }],
+                "asInt": "1024",
+            })
+        );
+
+        // the start time of the sums is kept across batches
+        let request = ExportRequest::from_records(&[record(3, 2048)], &mut start_times);
+        let json = request.to_json();
+        let sum = &json["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][2]["sum"];
+        assert_eq!(
+            sum["dataPoints"][0]["startTimeUnixNano"],
+            "1588327201000000000"
+        );
+    }
+
+    #[test]
+    fn it_skips_records_without_timestamp() {
+        let mut invalid = record(1, 512);
+        invalid["timestamp"] = json!("yesterday");
+
+        assert!(ExportRequest::from_records(&[invalid], &mut StartTimes::default()).is_empty());
+    }
+}
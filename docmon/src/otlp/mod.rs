@@ -0,0 +1,506 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::future::{BoxFuture, FutureExt};
+use hyper::{
+    body::HttpBody,
+    header::{HeaderName, HeaderValue, CONTENT_ENCODING},
+    Body, HeaderMap, Method, Request, StatusCode,
+};
+use hyper_tls::HttpsConnector;
+use log::debug;
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+
+use crate::{
+    client::ResponseError,
+    compress::Compression,
+    proxy::{Proxy, ProxyConnector},
+    sink::Sink,
+};
+
+mod metrics;
+mod proto;
+
+use metrics::{ExportRequest, StartTimes};
+
+const HTTP_PATH: &str = "/v1/metrics";
+const GRPC_PATH: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+/// Exports stats records as OTLP metrics to an OpenTelemetry collector.
+pub struct OtlpExporter {
+    url: String,
+    protocol: OtlpProtocol,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    compression: Compression,
+    client: hyper::Client<HttpsConnector<ProxyConnector>, Body>,
+    start_times: Mutex<StartTimes>,
+}
+
+impl OtlpExporter {
+    pub fn new(config: OtlpConfig) -> Result<Self> {
+        let (endpoint, protocol, headers, compression) = config.into_parts();
+
+        let parsed = Url::parse(&endpoint)?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(anyhow!("unsupported endpoint scheme: {}", endpoint));
+        }
+        // the TLS connector cannot negotiate h2 with ALPN
+        if protocol == OtlpProtocol::Grpc && parsed.scheme() == "https" {
+            return Err(anyhow!(
+                "grpc is only supported over plain http, use http/protobuf for {}",
+                endpoint
+            ));
+        }
+
+        let path = match protocol {
+            OtlpProtocol::Grpc => GRPC_PATH,
+            OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => HTTP_PATH,
+        };
+        let url = format!("{}{}", endpoint.trim_end_matches('/'), path);
+
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| {
+                let header = (name.parse()?, value.parse()?);
+                Ok(header)
+            })
+            .collect::<Result<Vec<_>>>()
+            .context("invalid otlp header")?;
+
+        // gRPC requires HTTP/2, which is negotiated without an upgrade
+        let proxy = Proxy::for_url(None, &url)?;
+        let client = hyper::Client::builder()
+            .http2_only(protocol == OtlpProtocol::Grpc)
+            .build(ProxyConnector::https(proxy)?);
+
+        Ok(Self {
+            url,
+            protocol,
+            headers,
+            compression,
+            client,
+            start_times: Mutex::new(StartTimes::default()),
+        })
+    }
+
+    pub async fn send(&self, items: &[Value]) -> Result<()> {
+        let request = {
+            let mut start_times = self.start_times.lock().expect("start times lock");
+            ExportRequest::from_records(items, &mut start_times)
+        };
+        if request.is_empty() {
+            return Ok(());
+        }
+
+        debug!("exporting metrics to {}", self.url);
+
+        match self.protocol {
+            OtlpProtocol::HttpProtobuf => {
+                self.post("application/x-protobuf", request.encode()).await
+            }
+            OtlpProtocol::HttpJson => {
+                let body = serde_json::to_vec(&request.to_json())?;
+                self.post("application/json", body).await
+            }
+            OtlpProtocol::Grpc => self.export_grpc(request.encode()).await,
+        }
+    }
+
+    fn request(&self, content_type: &str, body: Vec<u8>) -> Result<Request<Body>> {
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header("Content-Type", content_type);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+
+        Ok(builder.body(Body::from(body))?)
+    }
+
+    async fn post(&self, content_type: &str, body: Vec<u8>) -> Result<()> {
+        let mut req = self.request(content_type, self.compression.encode(body))?;
+        if let Some(encoding) = self.compression.content_encoding() {
+            req.headers_mut()
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        let res = self.client.request(req).await?;
+
+        if res.status().is_success() {
+            return Ok(());
+        }
+
+        Err(ResponseError::from_response(res).await?.into())
+    }
+
+    async fn export_grpc(&self, message: Vec<u8>) -> Result<()> {
+        // length-prefixed message, flagged when compressed
+        let encoding = self.compression.content_encoding();
+        let message = self.compression.encode(message);
+        let mut body = Vec::with_capacity(message.len() + 5);
+        body.push(encoding.is_some() as u8);
+        body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        body.extend_from_slice(&message);
+
+        let mut req = self.request("application/grpc", body)?;
+        req.headers_mut()
+            .insert("te", HeaderValue::from_static("trailers"));
+        if let Some(encoding) = encoding {
+            req.headers_mut()
+                .insert("grpc-encoding", HeaderValue::from_static(encoding));
+        }
+
+        let res = self.client.request(req).await?;
+        let status = res.status();
+        if !status.is_success() {
+            return Err(ResponseError::new(status, None, "").into());
+        }
+
+        // the status comes in trailers, or in headers when there is no response message
+        let (parts, mut body) = res.into_parts();
+        let trailers = if parts.headers.contains_key("grpc-status") {
+            parts.headers
+        } else {
+            while let Some(chunk) = body.data().await {
+                chunk?;
+            }
+            body.trailers().await?.unwrap_or_default()
+        };
+
+        grpc_status(&trailers)
+    }
+}
+
+// maps gRPC status codes to HTTP statuses with the same retry semantics
+fn grpc_status(trailers: &HeaderMap) -> Result<()> {
+    let code = trailers
+        .get("grpc-status")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u32>().ok())
+        .ok_or_else(|| anyhow!("grpc response without status"))?;
+
+    let status = match code {
+        0 => return Ok(()),
+        // DEADLINE_EXCEEDED
+        4 => StatusCode::GATEWAY_TIMEOUT,
+        // RESOURCE_EXHAUSTED
+        8 => StatusCode::TOO_MANY_REQUESTS,
+        // CANCELLED, ABORTED, OUT_OF_RANGE, UNAVAILABLE, DATA_LOSS
+        1 | 10 | 11 | 14 | 15 => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_REQUEST,
+    };
+
+    let message = trailers
+        .get("grpc-message")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let content = format!("grpc-status {}: {}", code, message);
+
+    Err(ResponseError::new(status, None, content).into())
+}
+
+/// OTLP transport, named as in `OTEL_EXPORTER_OTLP_PROTOCOL`.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum OtlpProtocol {
+    #[default]
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    #[serde(rename = "http/json")]
+    HttpJson,
+    #[serde(rename = "grpc")]
+    Grpc,
+}
+
+impl OtlpProtocol {
+    fn default_endpoint(self) -> &'static str {
+        match self {
+            OtlpProtocol::Grpc => "http://localhost:4317",
+            OtlpProtocol::HttpProtobuf | OtlpProtocol::HttpJson => "http://localhost:4318",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OtlpConfig {
+    endpoint: Option<String>,
+    #[serde(default)]
+    protocol: OtlpProtocol,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    compression: Compression,
+}
+
+impl OtlpConfig {
+    pub fn new(protocol: OtlpProtocol) -> Self {
+        Self {
+            endpoint: None,
+            protocol,
+            headers: BTreeMap::new(),
+            compression: Compression::default(),
+        }
+    }
+
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn into_parts(self) -> (String, OtlpProtocol, BTreeMap<String, String>, Compression) {
+        let protocol = self.protocol;
+        let endpoint = self
+            .endpoint
+            .unwrap_or_else(|| protocol.default_endpoint().to_string());
+
+        (endpoint, protocol, self.headers, self.compression)
+    }
+}
+
+impl Sink for OtlpExporter {
+    fn send<'a>(&'a self, _: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        OtlpExporter::send(self, items).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    use hyper::{body::Bytes, Response, Version};
+    use serde_json::json;
+
+    use super::{proto::Field, *};
+    use crate::{
+        mock::{self, RecordingServer},
+        retry::{self, Failure},
+    };
+
+    // an empty export response followed by `grpc-status` trailers for gRPC
+    struct Reply {
+        grpc: bool,
+        message: Option<Bytes>,
+        status: u32,
+    }
+
+    impl HttpBody for Reply {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Bytes, Infallible>>> {
+            let message = self.message.take().filter(|_| self.grpc);
+            Poll::Ready(message.map(Ok))
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Infallible>> {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", self.status.into());
+            Poll::Ready(Ok(Some(trailers).filter(|_| self.grpc)))
+        }
+    }
+
+    // stands in for an OpenTelemetry collector, answering gRPC requests with
+    // `grpc_status` and HTTP requests with the matching HTTP status
+    fn collector(grpc_status: u32) -> RecordingServer {
+        RecordingServer::start(move |request, _| {
+            let grpc = request.headers["Content-Type"] == "application/grpc";
+            let mut res = Response::new(Reply {
+                grpc,
+                message: Some(Bytes::from_static(&[0, 0, 0, 0, 0])),
+                status: grpc_status,
+            });
+            if grpc {
+                res.headers_mut()
+                    .insert("Content-Type", "application/grpc".parse().unwrap());
+            } else if grpc_status == 8 {
+                *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+            }
+            res
+        })
+    }
+
+    fn records() -> Vec<Value> {
+        vec![json!({
+            "timestamp": "2020-05-01T10:00:01.000Z",
+            "id": "0123456789ab",
+            "name": "web",
+            "cpu_percentage": 20.0,
+            "network_rx": 512,
+        })]
+    }
+
+    fn field(fields: &[(u32, Field)], number: u32) -> &Field {
+        &fields.iter().find(|(n, _)| *n == number).unwrap().1
+    }
+
+    // returns names of metrics and the first resource attribute
+    fn decode_request(bytes: &[u8]) -> (Vec<String>, String) {
+        let request = proto::decode(bytes);
+        let resource_metrics = field(&request, 1).fields();
+        let resource = field(&resource_metrics, 1).fields();
+        let attribute = field(&resource, 1).fields();
+        let value = field(&attribute, 2).fields();
+        let attribute = format!(
+            "{}={}",
+            field(&attribute, 1).string(),
+            field(&value, 1).string()
+        );
+
+        let scope_metrics = field(&resource_metrics, 2).fields();
+        let names = scope_metrics
+            .iter()
+            .filter(|(number, _)| *number == 2)
+            .map(|(_, metric)| field(&metric.fields(), 1).string().to_string())
+            .collect();
+
+        (names, attribute)
+    }
+
+    #[tokio::test]
+    async fn it_exports_http_protobuf() {
+        let collector = collector(0);
+        let config = OtlpConfig::new(OtlpProtocol::HttpProtobuf)
+            .with_endpoint(collector.url())
+            .with_header("Authorization", "Bearer token");
+        let exporter = OtlpExporter::new(config).unwrap();
+
+        exporter.send(&records()).await.unwrap();
+
+        let export = collector.requests()[0].clone();
+        assert_eq!(export.path, "/v1/metrics");
+        assert_eq!(export.headers["Content-Type"], "application/x-protobuf");
+        assert_eq!(export.headers["Authorization"], "Bearer token");
+
+        let (names, attribute) = decode_request(&export.body);
+        assert_eq!(
+            names,
+            vec!["container.cpu.utilization", "container.network.io"]
+        );
+        assert_eq!(attribute, "container.id=0123456789ab");
+    }
+
+    #[tokio::test]
+    async fn it_exports_http_json() {
+        let collector = collector(0);
+        let exporter = OtlpExporter::new(
+            OtlpConfig::new(OtlpProtocol::HttpJson).with_endpoint(collector.url()),
+        )
+        .unwrap();
+
+        exporter.send(&records()).await.unwrap();
+
+        let export = collector.requests()[0].clone();
+        assert_eq!(export.headers["Content-Type"], "application/json");
+        let body: Value = serde_json::from_slice(&export.body).unwrap();
+        assert_eq!(
+            body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"][0]["name"],
+            "container.cpu.utilization"
+        );
+    }
+
+    #[tokio::test]
+    async fn it_exports_grpc() {
+        let collector = collector(0);
+        let exporter =
+            OtlpExporter::new(OtlpConfig::new(OtlpProtocol::Grpc).with_endpoint(collector.url()))
+                .unwrap();
+
+        exporter.send(&records()).await.unwrap();
+
+        let export = collector.requests()[0].clone();
+        assert_eq!(export.path, GRPC_PATH);
+        assert_eq!(export.version, Version::HTTP_2);
+        assert_eq!(export.headers["te"], "trailers");
+
+        assert_eq!(export.body[0], 0);
+        let len = u32::from_be_bytes([
+            export.body[1],
+            export.body[2],
+            export.body[3],
+            export.body[4],
+        ]);
+        assert_eq!(len as usize, export.body.len() - 5);
+        let (names, _) = decode_request(&export.body[5..]);
+        assert_eq!(names.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_exports_compressed_metrics() {
+        let collector = collector(0);
+        for protocol in &[OtlpProtocol::HttpProtobuf, OtlpProtocol::Grpc] {
+            let config = OtlpConfig::new(*protocol)
+                .with_endpoint(collector.url())
+                .with_compression(Compression::Gzip);
+            OtlpExporter::new(config)
+                .unwrap()
+                .send(&records())
+                .await
+                .unwrap();
+        }
+
+        let exports = collector.requests();
+        assert_eq!(exports[0].headers["Content-Encoding"], "gzip");
+        let (names, _) = decode_request(&mock::gunzip(&exports[0].body));
+        assert_eq!(names.len(), 2);
+
+        assert_eq!(exports[1].headers["grpc-encoding"], "gzip");
+        assert_eq!(exports[1].body[0], 1);
+        let (names, _) = decode_request(&mock::gunzip(&exports[1].body[5..]));
+        assert_eq!(names.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_retries_exhausted_collector() {
+        for protocol in &[OtlpProtocol::Grpc, OtlpProtocol::HttpProtobuf] {
+            let collector = collector(8);
+            let exporter =
+                OtlpExporter::new(OtlpConfig::new(*protocol).with_endpoint(collector.url()))
+                    .unwrap();
+
+            let e = exporter.send(&records()).await.unwrap_err();
+            let response = e.downcast_ref::<ResponseError>().unwrap();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(retry::classify(&e), Failure::Retryable(None));
+        }
+    }
+
+    #[test]
+    fn it_rejects_grpc_over_https() {
+        let config = OtlpConfig::new(OtlpProtocol::Grpc).with_endpoint("https://localhost:4317");
+        assert!(OtlpExporter::new(config).is_err());
+
+        let config =
+            OtlpConfig::new(OtlpProtocol::HttpProtobuf).with_endpoint("https://localhost:4318");
+        assert!(OtlpExporter::new(config).is_ok());
+    }
+
+    #[test]
+    fn it_uses_default_endpoint_of_protocol() {
+        let (endpoint, ..) = OtlpConfig::new(OtlpProtocol::Grpc).into_parts();
+        assert_eq!(endpoint, "http://localhost:4317");
+
+        let (endpoint, ..) = OtlpConfig::new(OtlpProtocol::HttpJson).into_parts();
+        assert_eq!(endpoint, "http://localhost:4318");
+    }
+}
@@ -0,0 +1,179 @@
+/// A protobuf message being encoded. Only wire types used by OTLP metrics
+/// are supported.
+#[derive(Debug, Default)]
+pub struct Message(Vec<u8>);
+
+const VARINT: u32 = 0;
+const FIXED64: u32 = 1;
+const LENGTH_DELIMITED: u32 = 2;
+
+impl Message {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn varint(&mut self, field: u32, value: u64) -> &mut Self {
+        self.key(field, VARINT);
+        self.raw_varint(value);
+        self
+    }
+
+    pub fn bool(&mut self, field: u32, value: bool) -> &mut Self {
+        self.varint(field, value as u64)
+    }
+
+    pub fn fixed64(&mut self, field: u32, value: u64) -> &mut Self {
+        self.key(field, FIXED64);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn sfixed64(&mut self, field: u32, value: i64) -> &mut Self {
+        self.key(field, FIXED64);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    pub fn double(&mut self, field: u32, value: f64) -> &mut Self {
+        self.key(field, FIXED64);
+        self.0.extend_from_slice(&value.to_le_bytes());
+        self
+    }
+
+    /// Writes a string field, empty strings are omitted as proto3 defaults.
+    pub fn string(&mut self, field: u32, value: &str) -> &mut Self {
+        if !value.is_empty() {
+            self.bytes(field, value.as_bytes());
+        }
+        self
+    }
+
+    pub fn message(&mut self, field: u32, value: &Message) -> &mut Self {
+        self.bytes(field, &value.0)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Writes a length-delimited field even if it is empty, as members of
+    /// `oneof` have to be.
+    pub fn bytes(&mut self, field: u32, value: &[u8]) -> &mut Self {
+        self.key(field, LENGTH_DELIMITED);
+        self.raw_varint(value.len() as u64);
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    fn key(&mut self, field: u32, wire_type: u32) {
+        self.raw_varint(u64::from(field << 3 | wire_type));
+    }
+
+    fn raw_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.0.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.0.push(value as u8);
+    }
+}
+
+/// A decoded field of a protobuf message.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Varint(u64),
+    Fixed64([u8; 8]),
+    Bytes(Vec<u8>),
+}
+
+#[cfg(test)]
+impl Field {
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            Field::Bytes(bytes) => bytes,
+            _ => panic!("not a length-delimited field: {:?}", self),
+        }
+    }
+
+    pub fn string(&self) -> &str {
+        std::str::from_utf8(self.bytes()).unwrap()
+    }
+
+    pub fn fields(&self) -> Vec<(u32, Field)> {
+        decode(self.bytes())
+    }
+}
+
+/// Splits a message into its fields, panics on malformed input.
+#[cfg(test)]
+pub fn decode(mut bytes: &[u8]) -> Vec<(u32, Field)> {
+    fn varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = bytes[0];
+            *bytes = &bytes[1..];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        value
+    }
+
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let key = varint(&mut bytes);
+        let field = match key as u32 & 7 {
+            VARINT => Field::Varint(varint(&mut bytes)),
+            FIXED64 => {
+                let mut value = [0; 8];
+                value.copy_from_slice(&bytes[..8]);
+                bytes = &bytes[8..];
+                Field::Fixed64(value)
+            }
+            LENGTH_DELIMITED => {
+                let len = varint(&mut bytes) as usize;
+                let value = bytes[..len].to_vec();
+                bytes = &bytes[len..];
+                Field::Bytes(value)
+            }
+            wire_type => panic!("unsupported wire type {}", wire_type),
+        };
+        fields.push(((key >> 3) as u32, field));
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_fields() {
+        let mut inner = Message::new();
+        inner.string(1, "a").string(2, "");
+
+        let mut message = Message::new();
+        message
+            .varint(1, 300)
+            .message(2, &inner)
+            .double(3, 1.5)
+            .bool(4, true);
+
+        let bytes = message.into_bytes();
+        assert_eq!(
+            bytes,
+            [
+                0x08, 0xac, 0x02, 0x12, 0x03, 0x0a, 0x01, b'a', 0x19, 0, 0, 0, 0, 0, 0, 0xf8, 0x3f,
+                0x20, 0x01
+            ]
+        );
+
+        let fields = decode(&bytes);
+        assert_eq!(fields[0], (1, Field::Varint(300)));
+        assert_eq!(fields[1].1.fields(), vec![(1, Field::Bytes(b"a".to_vec()))]);
+        assert_eq!(fields[2], (3, Field::Fixed64(1.5f64.to_le_bytes())));
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Result;
+use config::ConfigError;
+use serde::Deserialize;
+
+use crate::{
+    Client, ClientConfig, FileConfig, FileWriter, FilterConfig, InfluxClient, InfluxConfig,
+    IngestionClient, IngestionConfig, OtlpConfig, OtlpExporter, PrometheusConfig,
+    PrometheusExporter, PublisherConfig, Sink, StatsdClient, StatsdConfig, StdoutConfig,
+    StdoutWriter,
+};
+
+/// A destination for stats records along with its own batching and filters.
+#[derive(Debug, Deserialize)]
+pub struct OutputConfig {
+    #[serde(flatten)]
+    sink: SinkConfig,
+    #[serde(flatten)]
+    publisher: PublisherConfig,
+    #[serde(default)]
+    filter: FilterConfig,
+}
+
+impl OutputConfig {
+    pub fn new(sink: SinkConfig, publisher: PublisherConfig, filter: FilterConfig) -> Self {
+        Self {
+            sink,
+            publisher,
+            filter,
+        }
+    }
+
+    /// Replaces the sink with stdout and drops the spool, keeping batching
+    /// and filters, so nothing leaves the host.
+    pub fn into_dry_run(self, pretty: bool) -> Self {
+        Self {
+            sink: SinkConfig::Stdout(StdoutConfig::new(pretty)),
+            publisher: self.publisher.without_spool(),
+            filter: self.filter,
+        }
+    }
+
+    pub fn publisher(&self) -> &PublisherConfig {
+        &self.publisher
+    }
+
+    /// Requires batching settings for every output but prometheus, which is
+    /// scraped and refreshed every 5 seconds unless `interval` is set, and
+    /// checks the publisher settings.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !matches!(self.sink, SinkConfig::Prometheus(_)) && !self.publisher.has_batching() {
+            return Err(ConfigError::Message(
+                "log_name, batch_size and interval are required for every output but prometheus"
+                    .to_string(),
+            ));
+        }
+
+        self.publisher.validate()
+    }
+
+    pub fn into_parts(self) -> (SinkConfig, PublisherConfig, FilterConfig) {
+        (self.sink, self.publisher, self.filter)
+    }
+}
+
+/// Backend specific settings selected by the `type` key of an output.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// Azure Monitor HTTP Data Collector API.
+    DataCollector(ClientConfig),
+    /// Azure Monitor Logs Ingestion API, `log_name` is the DCR stream name.
+    LogsIngestion(IngestionConfig),
+    /// JSON Lines file with rotation, `log_name` is not used.
+    File(FileConfig),
+    /// InfluxDB or another service accepting line protocol, `log_name` is not used.
+    Influx(InfluxConfig),
+    /// OpenTelemetry collector receiving OTLP metrics, `log_name` is not used.
+    Otlp(OtlpConfig),
+    /// Prometheus `/metrics` endpoint, `log_name` is not used.
+    Prometheus(PrometheusConfig),
+    /// Prints batches to stdout instead of sending them.
+    Stdout(StdoutConfig),
+    /// StatsD or DogStatsD agent receiving gauges, `log_name` is not used.
+    Statsd(StatsdConfig),
+}
+
+impl SinkConfig {
+    pub fn build(self) -> Result<Box<dyn Sink>> {
+        match self {
+            SinkConfig::DataCollector(config) => Ok(Box::new(Client::new(config)?)),
+            SinkConfig::LogsIngestion(config) => Ok(Box::new(IngestionClient::new(config)?)),
+            SinkConfig::File(config) => Ok(Box::new(FileWriter::new(config)?)),
+            SinkConfig::Influx(config) => Ok(Box::new(InfluxClient::new(config)?)),
+            SinkConfig::Otlp(config) => Ok(Box::new(OtlpExporter::new(config)?)),
+            SinkConfig::Prometheus(config) => Ok(Box::new(PrometheusExporter::new(config)?)),
+            SinkConfig::Stdout(config) => Ok(Box::new(StdoutWriter::new(config))),
+            SinkConfig::Statsd(config) => Ok(Box::new(StatsdClient::new(config)?)),
+        }
+    }
+}
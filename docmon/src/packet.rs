@@ -0,0 +1,33 @@
+/// Packs newline terminated lines into datagrams of up to `max_packet_size`
+/// bytes, a line longer than the limit is sent alone.
+pub fn pack(lines: &[String], max_packet_size: usize) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + line.len() > max_packet_size {
+            packets.push(std::mem::take(&mut packet));
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_packs_lines_into_packets() {
+        let lines = vec!["a".repeat(6), "b".repeat(4), "c".repeat(12), "d".repeat(2)];
+
+        let packets = pack(&lines, 10);
+
+        assert_eq!(
+            packets,
+            vec!["a".repeat(6) + "bbbb", "c".repeat(12), "dd".to_string()]
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+use futures_util::future::{BoxFuture, FutureExt};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::info;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::sink::Sink;
+
+const CONTENT_TYPE_OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Gauge,
+    Counter,
+}
+
+struct Metric {
+    name: &'static str,
+    kind: Kind,
+    help: &'static str,
+    field: &'static str,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "container_cpu_percent",
+        kind: Kind::Gauge,
+        help: "CPU usage in percent, 100 per fully used CPU.",
+        field: "cpu_percentage",
+    },
+    Metric {
+        name: "container_memory_bytes",
+        kind: Kind::Gauge,
+        help: "Memory usage excluding page cache in bytes.",
+        field: "memory",
+    },
+    Metric {
+        name: "container_memory_percent",
+        kind: Kind::Gauge,
+        help: "Memory usage in percent of the memory limit.",
+        field: "memory_percentage",
+    },
+    Metric {
+        name: "container_memory_limit_bytes",
+        kind: Kind::Gauge,
+        help: "Memory limit in bytes.",
+        field: "memory_limit",
+    },
+    Metric {
+        name: "container_network_rx_bytes",
+        kind: Kind::Counter,
+        help: "Bytes received over all network interfaces.",
+        field: "network_rx",
+    },
+    Metric {
+        name: "container_network_tx_bytes",
+        kind: Kind::Counter,
+        help: "Bytes sent over all network interfaces.",
+        field: "network_tx",
+    },
+    Metric {
+        name: "container_block_read_bytes",
+        kind: Kind::Counter,
+        help: "Bytes read from block devices.",
+        field: "block_read",
+    },
+    Metric {
+        name: "container_block_write_bytes",
+        kind: Kind::Counter,
+        help: "Bytes written to block devices.",
+        field: "block_write",
+    },
+    Metric {
+        name: "container_pids",
+        kind: Kind::Gauge,
+        help: "Number of processes and threads.",
+        field: "pid",
+    },
+];
+
+/// Keeps the latest stats record of every container and serves them as
+/// OpenMetrics on `GET /metrics` for Prometheus to scrape.
+pub struct PrometheusExporter {
+    registry: Arc<Mutex<Registry>>,
+    local_addr: SocketAddr,
+}
+
+impl PrometheusExporter {
+    /// Binds the HTTP listener and starts serving scrapes in the background.
+    pub fn new(config: PrometheusConfig) -> Result<Self> {
+        let (listen, grace_period) = config.into_parts();
+        let addr = listen
+            .parse::<SocketAddr>()
+            .with_context(|| format!("invalid listen address {}", listen))?;
+
+        let registry = Arc::new(Mutex::new(Registry::new(grace_period)));
+
+        let shared = registry.clone();
+        let make_svc = make_service_fn(move |_| {
+            let registry = shared.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| scrape(registry.clone(), req))) }
+        });
+
+        let server = Server::try_bind(&addr)
+            .with_context(|| format!("unable to listen on {}", addr))?
+            .serve(make_svc);
+        let local_addr = server.local_addr();
+        tokio::spawn(server);
+
+        info!(
+            "serving prometheus metrics on http://{}/metrics",
+            local_addr
+        );
+
+        Ok(Self {
+            registry,
+            local_addr,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Replaces the latest values of containers found in `items`.
+    pub fn update(&self, items: &[Value]) {
+        let mut registry = self.registry.lock().expect("registry lock poisoned");
+        let now = Instant::now();
+        for item in items {
+            registry.update(item, now);
+        }
+    }
+}
+
+async fn scrape(
+    registry: Arc<Mutex<Registry>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let mut res = Response::new(Body::empty());
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        *res.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(res);
+    }
+
+    let text = registry
+        .lock()
+        .expect("registry lock poisoned")
+        .render(Instant::now());
+
+    *res.body_mut() = Body::from(text);
+    res.headers_mut()
+        .insert(CONTENT_TYPE, CONTENT_TYPE_OPENMETRICS.parse().unwrap());
+    Ok(res)
+}
+
+struct Series {
+    labels: String,
+    record: Value,
+    updated: Instant,
+}
+
+struct Registry {
+    // keyed by container id
+    series: BTreeMap<String, Series>,
+    grace_period: Duration,
+}
+
+impl Registry {
+    fn new(grace_period: Duration) -> Self {
+        Self {
+            series: BTreeMap::new(),
+            grace_period,
+        }
+    }
+
+    fn update(&mut self, record: &Value, now: Instant) {
+        let id = match record["id"].as_str() {
+            Some(id) => id.to_string(),
+            None => return,
+        };
+
+        let series = Series {
+            labels: labels(record),
+            record: record.clone(),
+            updated: now,
+        };
+        self.series.insert(id, series);
+    }
+
+    // series of containers which stopped reporting are removed after the grace period
+    fn render(&mut self, now: Instant) -> String {
+        let grace_period = self.grace_period;
+        self.series
+            .retain(|_, series| now.duration_since(series.updated) <= grace_period);
+
+        let mut text = String::new();
+        for metric in METRICS {
+            let (kind, suffix) = match metric.kind {
+                Kind::Gauge => ("gauge", ""),
+                Kind::Counter => ("counter", "_total"),
+            };
+            let _ = writeln!(text, "# TYPE {} {}", metric.name, kind);
+            let _ = writeln!(text, "# HELP {} {}", metric.name, metric.help);
+
+            for series in self.series.values() {
+                let value = &series.record[metric.field];
+                if value.is_number() {
+                    let _ = writeln!(
+                        text,
+                        "{}{}{{{}}} {}",
+                        metric.name, suffix, series.labels, value
+                    );
+                }
+            }
+        }
+        text.push_str("# EOF\n");
+
+        text
+    }
+}
+
+// renders `id`, `name`, `image` and container labels selected by the
+// collector metadata config as `label_<key>`
+fn labels(record: &Value) -> String {
+    let mut labels = Vec::new();
+    for key in &["id", "name", "image"] {
+        if let Some(value) = record[key].as_str() {
+            labels.push((key.to_string(), value));
+        }
+    }
+
+    if let Some(container_labels) = record["labels"].as_object() {
+        for (key, value) in container_labels {
+            if let Some(value) = value.as_str() {
+                labels.push((format!("label_{}", sanitize(key)), value));
+            }
+        }
+    }
+
+    labels
+        .iter()
+        .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PrometheusConfig {
+    listen: String,
+    grace_period: u64,
+}
+
+impl Default for PrometheusConfig {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:9417".to_string(),
+            grace_period: 300,
+        }
+    }
+}
+
+impl PrometheusConfig {
+    pub fn new(listen: impl Into<String>, grace_period: u64) -> Self {
+        Self {
+            listen: listen.into(),
+            grace_period,
+        }
+    }
+
+    pub fn into_parts(self) -> (String, Duration) {
+        (self.listen, Duration::from_secs(self.grace_period))
+    }
+}
+
+impl Sink for PrometheusExporter {
+    fn send<'a>(&'a self, _: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        self.update(items);
+        futures_util::future::ok(()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::body;
+    use serde_json::json;
+
+    use super::*;
+
+    fn record(id: &str, name: &str) -> Value {
+        json!({
+            "timestamp": "2020-05-01T10:00:01.000Z",
+            "id": id,
+            "name": name,
+            "cpu_percentage": 20.0,
+            "memory": 1024,
+            "memory_percentage": null,
+            "network_rx": 512,
+            "image": "nginx:1.17",
+            "labels": { "com.example.tier": "front \"a\"" },
+        })
+    }
+
+    #[test]
+    fn it_removes_stale_series_after_grace_period() {
+        let mut registry = Registry::new(Duration::from_secs(60));
+        let start = Instant::now();
+
+        registry.update(&record("0123456789ab", "web"), start);
+        registry.update(
+            &record("ba9876543210", "db"),
+            start + Duration::from_secs(30),
+        );
+
+        let text = registry.render(start + Duration::from_secs(60));
+        assert!(text.contains("name=\"web\""));
+        assert!(text.contains("name=\"db\""));
+
+        let text = registry.render(start + Duration::from_secs(61));
+        assert!(!text.contains("name=\"web\""));
+        assert!(text.contains("name=\"db\""));
+    }
+
+    #[tokio::test]
+    async fn it_serves_openmetrics() {
+        let exporter = PrometheusExporter::new(PrometheusConfig::new("127.0.0.1:0", 60)).unwrap();
+        exporter.update(&[record("0123456789ab", "web")]);
+
+        let url = format!("http://{}/metrics", exporter.local_addr());
+        let res = hyper::Client::new()
+            .get(url.parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], CONTENT_TYPE_OPENMETRICS);
+
+        let bytes = body::to_bytes(res.into_body()).await.unwrap();
+        let text = String::from_utf8(bytes.to_vec()).unwrap();
+        let labels = r#"id="0123456789ab",name="web",image="nginx:1.17",label_com_example_tier="front \"a\"""#;
+
+        assert!(text.contains("# TYPE container_cpu_percent gauge\n"));
+        assert!(text.contains(&format!("container_cpu_percent{{{}}} 20.0\n", labels)));
+        assert!(text.contains(&format!("container_memory_bytes{{{}}} 1024\n", labels)));
+        assert!(text.contains("# TYPE container_network_rx_bytes counter\n"));
+        assert!(text.contains(&format!(
+            "container_network_rx_bytes_total{{{}}} 512\n",
+            labels
+        )));
+        assert!(!text.contains("container_memory_percent{"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
@@ -1,171 +1,1065 @@
-use std::time::Duration;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
-use futures_util::{select, FutureExt, StreamExt};
-use log::{debug, info, warn};
+use anyhow::Result;
+use config::ConfigError;
+use futures_util::{select, FutureExt};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
-use tokio::{
-    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
-    time,
-};
+use serde_json::Value;
+use tokio::time::{self, Instant};
 
-use crate::Client;
+use crate::{
+    backoff::Backoff,
+    channel::{self, ChannelConfig, Overflow, Receiver, Sender},
+    retry::{self, Decision, Failure, RetryConfig, RetryPolicy},
+    sink::{Limits, Sink},
+    spool::{AsyncSpool, Spool, SpoolConfig},
+};
 
 pub struct Publisher<D> {
-    receiver: UnboundedReceiver<D>,
-    client: Client,
+    receiver: Receiver<D>,
+    sink: Box<dyn Sink>,
+    limits: Limits,
+    reshaped: Arc<Reshaped>,
     log_name: String,
     interval: Duration,
     batch_size: usize,
+    retry: RetryConfig,
+    spool: Option<AsyncSpool>,
+    backoff: Backoff,
+    resume_at: Option<Instant>,
+    parked: VecDeque<Parked>,
+    reported_dropped: u64,
+    shutdown_timeout: Duration,
+}
+
+// a batch waiting for its next send attempt
+struct Parked {
+    batch: Vec<Value>,
+    policy: RetryPolicy,
 }
 
 impl<D> Publisher<D>
 where
     D: Serialize + std::fmt::Debug,
 {
-    pub fn new(client: Client, config: PublisherConfig) -> (Publisher<D>, PublisherHandle<D>) {
-        let (log_name, batch_size, interval) = config.into_parts();
-        let (sender, receiver) = mpsc::unbounded_channel();
+    pub fn new(
+        sink: impl Sink + 'static,
+        config: PublisherConfig,
+    ) -> Result<(Publisher<D>, PublisherHandle<D>)> {
+        config.validate()?;
+        let (log_name, batch_size, interval, retry, spool, channel, shutdown_timeout) =
+            config.into_parts();
+        let spool = spool.map(Spool::open).transpose()?.map(AsyncSpool::new);
+        let backoff = retry.backoff();
+        let (sender, receiver) = channel::channel(channel);
+        let reshaped = Arc::new(Reshaped::default());
 
         let publisher = Publisher {
             receiver,
-            client,
+            limits: sink.limits(),
+            sink: Box::new(sink),
+            reshaped: reshaped.clone(),
             log_name,
             batch_size,
             interval,
+            retry,
+            spool,
+            backoff,
+            resume_at: None,
+            parked: VecDeque::new(),
+            reported_dropped: 0,
+            shutdown_timeout,
         };
-        let handle = PublisherHandle(sender);
+        let handle = PublisherHandle { sender, reshaped };
 
-        (publisher, handle)
+        Ok((publisher, handle))
     }
 
-    pub async fn run(mut self) {
+    /// Publishes batches until all handles are dropped or a stop is requested.
+    /// Returns a number of items which could be neither sent nor spooled.
+    pub async fn run(mut self) -> usize {
         info!("starting publisher");
 
+        let stop = self.receiver.stop();
         let mut items = Vec::with_capacity(self.batch_size);
-        let mut stop_requested = false;
+
+        // ticks at a fixed period, a flush triggered by a full batch does not
+        // postpone the next one
+        let mut ticker = time::interval_at(Instant::now() + self.interval, self.interval);
 
         loop {
-            select! {
-                closed = collect(&mut self.receiver, &mut items).fuse() => {
-                    debug!("collected {} item(s)", items.len());
-                    stop_requested = closed;
+            // parked batches hold at most a channel worth of items, beyond
+            // that items stay in the channel and its overflow policy applies
+            let full = self.parked_items() >= self.receiver.capacity();
+            let receiver = &mut self.receiver;
+            let receive = async {
+                if full {
+                    debug!("parked batches are full, leaving items in channel");
+                    stop.requested().await;
+                    return None;
+                }
+                Some(receiver.recv().await)
+            };
+
+            let mut closed = false;
+            let flush = select! {
+                item = receive.fuse() => match item {
+                    Some(Some(item)) => {
+                        items.push(item);
+                        items.len() >= self.batch_size
+                    }
+                    Some(None) => {
+                        info!("channel is closed");
+                        closed = true;
+                        true
+                    }
+                    // the final flush takes over
+                    None => true,
                 },
-                _ = time::delay_for(self.interval).fuse() => {
-                    debug!("default interval expired");
+                _ = ticker.tick().fuse() => {
+                    debug!("interval elapsed with {} item(s)", items.len());
+                    true
                 }
+            };
+            if !flush {
+                continue;
+            }
+
+            // whatever is left goes through the bounded final flush
+            if closed || stop.is_requested() {
+                break;
             }
 
             if !items.is_empty() {
-                info!("sending data: {} item(s)", items.len());
-                if let Err(e) = self.client.send(&self.log_name, &items).await {
-                    warn!("cannot send data: {}", e);
-                } else {
-                    info!("successfully sent data");
-                    items.clear();
+                let batch = std::mem::replace(&mut items, Vec::with_capacity(self.batch_size));
+                for batch in self.fit(serialize(batch)) {
+                    let unspooled = match self.spool.as_ref() {
+                        Some(spool) => {
+                            spool
+                                .run(move |spool| match spool.push(&batch) {
+                                    Ok(()) => {
+                                        debug!("spooled {} item(s)", batch.len());
+                                        None
+                                    }
+                                    Err(e) => {
+                                        error!("unable to spool data: {}", e);
+                                        Some(batch)
+                                    }
+                                })
+                                .await
+                        }
+                        None => Some(batch),
+                    };
+
+                    if let Some(batch) = unspooled {
+                        self.park(batch);
+                    }
                 }
             } else {
                 info!("no items to send")
             }
 
-            if stop_requested {
-                info!("stopping publisher");
-                break;
+            // a batch is removed only once it is sent, so whatever the stop
+            // interrupts is kept for the final flush
+            let send = async {
+                self.send_parked().await;
+                self.replay().await;
+            };
+            select! {
+                _ = send.fuse() => {},
+                _ = stop.requested().fuse() => {},
+            }
+            self.report_dropped();
+        }
+
+        info!("stopping publisher");
+        while let Some(item) = self.receiver.recv().await {
+            items.push(item);
+        }
+        let mut pending = std::mem::take(&mut self.parked);
+        for batch in serialize(items).chunks(self.batch_size.max(1)) {
+            for batch in self.fit(batch.to_vec()) {
+                pending.push_back(Parked {
+                    batch,
+                    policy: RetryPolicy::new(&self.retry),
+                });
             }
         }
+        let lost = self.shutdown(pending).await;
 
         info!("publisher stopped");
+        lost
     }
-}
 
-async fn collect<D>(receiver: &mut UnboundedReceiver<D>, items: &mut Vec<D>) -> bool {
-    loop {
-        if let Some(item) = receiver.next().await {
-            debug!("new item available in the channel");
-            items.push(item);
+    // sends what is left within the shutdown timeout, spooling it first when
+    // a spool is configured, and reports records which could not be delivered
+    async fn shutdown(&mut self, mut pending: VecDeque<Parked>) -> usize {
+        let deadline = Instant::now() + self.shutdown_timeout;
+
+        if let Some(spool) = self.spool.as_ref() {
+            pending = spool
+                .run(move |spool| {
+                    while let Some(parked) = pending.pop_front() {
+                        if let Err(e) = spool.push(&parked.batch) {
+                            error!("unable to spool data: {}", e);
+                            pending.push_front(parked);
+                            break;
+                        }
+                    }
+                    pending
+                })
+                .await;
+        }
+        self.resume_at = None;
 
-            if items.len() >= items.capacity() {
-                info!("items batch is full");
-                return false;
+        let mut rejected = 0;
+        let flush = async {
+            while let Some(parked) = pending.front_mut() {
+                match self.publish(&parked.batch, &mut parked.policy).await {
+                    Ok(Outcome::Delivered) => {
+                        pending.pop_front();
+                    }
+                    Ok(Outcome::Rejected) => {
+                        rejected += parked.batch.len();
+                        pending.pop_front();
+                    }
+                    Err(e) => {
+                        warn!("final flush failed: {:#}", e);
+                        break;
+                    }
+                }
+            }
+            self.replay().await;
+        };
+        if time::timeout_at(deadline, flush).await.is_err() {
+            warn!(
+                "final flush did not complete within {:?}",
+                self.shutdown_timeout
+            );
+        }
+
+        self.report_dropped();
+        if let Some(spool) = self.spool.as_ref() {
+            let spooled = spool.run(|spool| spool.len()).await;
+            if spooled > 0 {
+                info!("{} batch(es) left in spool", spooled);
             }
-        } else {
-            info!("channel is closed");
-            return true;
         }
+
+        let lost = rejected
+            + pending
+                .iter()
+                .map(|parked| parked.batch.len())
+                .sum::<usize>();
+        if lost > 0 {
+            error!("unable to deliver {} item(s) before shutdown", lost);
+        }
+        lost
     }
+
+    fn report_dropped(&mut self) {
+        let dropped = self.receiver.dropped();
+        if dropped > self.reported_dropped {
+            warn!(
+                "channel is full, dropped {} item(s) ({} total)",
+                dropped - self.reported_dropped,
+                dropped
+            );
+            self.reported_dropped = dropped;
+        }
+    }
+
+    // truncates oversized fields and splits the batch into as many as the
+    // limits of the sink need, each of them is retried on its own
+    fn fit(&self, mut batch: Vec<Value>) -> Vec<Vec<Value>> {
+        let truncated = batch
+            .iter_mut()
+            .map(|record| self.limits.truncate(record))
+            .filter(|truncated| *truncated)
+            .count();
+        if truncated > 0 {
+            warn!("truncated oversized fields in {} record(s)", truncated);
+            self.reshaped
+                .truncated
+                .fetch_add(truncated as u64, Ordering::Relaxed);
+        }
+
+        let len = batch.len();
+        let batches = self.limits.split(batch);
+        if batches.len() > 1 {
+            info!("split {} item(s) into {} batches", len, batches.len());
+            self.reshaped.split.fetch_add(len as u64, Ordering::Relaxed);
+        }
+        batches
+    }
+
+    fn parked_items(&self) -> usize {
+        self.parked.iter().map(|parked| parked.batch.len()).sum()
+    }
+
+    fn park(&mut self, batch: Vec<Value>) {
+        self.parked.push_back(Parked {
+            batch,
+            policy: RetryPolicy::new(&self.retry),
+        });
+    }
+
+    // sends parked batches oldest first until sending fails, a failed batch
+    // waits for its retry delay while new items keep being collected
+    async fn send_parked(&mut self) {
+        if let Some(resume_at) = self.resume_at {
+            if Instant::now() < resume_at {
+                // batches queued behind a failing one age out like it does
+                while let Some(parked) = self.parked.front() {
+                    if !parked.policy.is_expired() {
+                        break;
+                    }
+                    error!(
+                        "dropping {} item(s): not sent within retry max_age",
+                        parked.batch.len()
+                    );
+                    self.parked.pop_front();
+                }
+
+                debug!("postponing {} parked batch(es)", self.parked.len());
+                return;
+            }
+        }
+
+        while let Some(parked) = self.parked.front_mut() {
+            info!("sending data: {} item(s)", parked.batch.len());
+            let e = match self.sink.send(&self.log_name, &parked.batch).await {
+                Ok(()) => {
+                    info!("successfully sent data");
+                    self.parked.pop_front();
+                    self.resume_at = None;
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            let len = parked.batch.len();
+            match parked.policy.on_error(&e) {
+                Decision::Retry(delay) => {
+                    warn!("cannot send data: {}. retrying in {:?}", e, delay);
+                    self.resume_at = Some(Instant::now() + delay);
+                    return;
+                }
+                Decision::Reject => {
+                    error!("data rejected, dropping {} item(s): {}", len, e);
+                }
+                Decision::GiveUp => {
+                    error!(
+                        "dropping {} item(s): cannot send data after {} attempt(s): {}",
+                        len,
+                        parked.policy.attempts(),
+                        e
+                    );
+                }
+            }
+            self.parked.pop_front();
+        }
+    }
+
+    // sends spooled batches oldest first until the spool is empty or sending fails
+    async fn replay(&mut self) {
+        let spool = match self.spool.clone() {
+            Some(spool) => spool,
+            None => return,
+        };
+
+        let pending = spool
+            .run(|spool| {
+                spool.evict();
+                spool.len()
+            })
+            .await;
+
+        if let Some(resume_at) = self.resume_at {
+            if Instant::now() < resume_at {
+                debug!("postponing {} spooled batch(es)", pending);
+                return;
+            }
+        }
+
+        loop {
+            let (batch, pending) = spool.run(|spool| (spool.front(), spool.len())).await;
+            let batch = match batch {
+                Some(Ok(batch)) => batch,
+                Some(Err(e)) => {
+                    error!("unable to read spooled data, dropping batch: {}", e);
+                    spool.run(Spool::pop).await;
+                    continue;
+                }
+                None => return,
+            };
+
+            info!(
+                "sending spooled data: {} item(s), {} batch(es) pending",
+                batch.len(),
+                pending
+            );
+            let e = match self.sink.send(&self.log_name, &batch).await {
+                Ok(()) => {
+                    info!("successfully sent data");
+                    spool.run(Spool::pop).await;
+                    self.backoff.reset();
+                    self.resume_at = None;
+                    continue;
+                }
+                Err(e) => e,
+            };
+
+            match retry::classify(&e) {
+                Failure::Permanent => {
+                    error!("data rejected, dropping {} item(s): {}", batch.len(), e);
+                    spool.run(Spool::pop).await;
+                }
+                Failure::Retryable(retry_after) => {
+                    let backoff = &mut self.backoff;
+                    let delay = retry_after.unwrap_or_else(|| backoff.next_delay());
+                    warn!(
+                        "cannot send data: {}. keeping {} batch(es) spooled for {:?}",
+                        e, pending, delay
+                    );
+                    self.resume_at = Some(Instant::now() + delay);
+                    return;
+                }
+            }
+        }
+    }
+
+    // sends a batch retrying failures in place, returns an error when giving up
+    async fn publish(&self, items: &[Value], policy: &mut RetryPolicy) -> Result<Outcome> {
+        loop {
+            info!("sending data: {} item(s)", items.len());
+            let e = match self.sink.send(&self.log_name, items).await {
+                Ok(()) => {
+                    info!("successfully sent data");
+                    return Ok(Outcome::Delivered);
+                }
+                Err(e) => e,
+            };
+
+            match policy.on_error(&e) {
+                Decision::Retry(delay) => {
+                    warn!("cannot send data: {}. retrying in {:?}", e, delay);
+                    time::delay_for(delay).await;
+                }
+                Decision::Reject => {
+                    error!("data rejected, dropping {} item(s): {}", items.len(), e);
+                    return Ok(Outcome::Rejected);
+                }
+                Decision::GiveUp => {
+                    return Err(e.context(format!(
+                        "cannot send data after {} attempt(s)",
+                        policy.attempts()
+                    )));
+                }
+            }
+        }
+    }
+}
+
+/// How a batch left `Publisher::publish` without an error.
+enum Outcome {
+    Delivered,
+    Rejected,
+}
+
+fn serialize<D>(items: Vec<D>) -> Vec<Value>
+where
+    D: Serialize + std::fmt::Debug,
+{
+    items
+        .into_iter()
+        .filter_map(|item| match serde_json::to_value(&item) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                error!("unable to serialize {:?}, dropping it: {}", item, e);
+                None
+            }
+        })
+        .collect()
+}
+
+// counts batches and records changed to fit the limits of the sink
+#[derive(Debug, Default)]
+struct Reshaped {
+    split: AtomicU64,
+    truncated: AtomicU64,
 }
 
 #[derive(Debug, Clone)]
-pub struct PublisherHandle<D>(UnboundedSender<D>);
+pub struct PublisherHandle<D> {
+    sender: Sender<D>,
+    reshaped: Arc<Reshaped>,
+}
 
 impl<D> PublisherHandle<D>
 where
     D: std::fmt::Debug,
 {
-    pub fn send(&self, data: D) {
-        if let Err(e) = self.0.send(data) {
+    pub async fn send(&self, data: D) {
+        if let Err(e) = self.sender.send(data).await {
             warn!("Unable to send a message to channel: {:?}", e);
         }
     }
+
+    /// Asks the publisher to send what is queued and stop, even while other
+    /// handles are still alive.
+    pub fn stop(&self) {
+        self.sender.stop();
+    }
+
+    /// Returns a number of items dropped because the channel was full.
+    pub fn dropped(&self) -> u64 {
+        self.sender.dropped()
+    }
+
+    /// Returns a number of items in batches split to stay within the sink limits.
+    pub fn split(&self) -> u64 {
+        self.reshaped.split.load(Ordering::Relaxed)
+    }
+
+    /// Returns a number of items with fields truncated to the sink limits.
+    pub fn truncated(&self) -> u64 {
+        self.reshaped.truncated.load(Ordering::Relaxed)
+    }
+
+    /// Returns a number of items waiting to be published.
+    pub fn queued(&self) -> usize {
+        self.sender.queued()
+    }
+
+    /// Returns the capacity of the channel to the publisher.
+    pub fn capacity(&self) -> usize {
+        self.sender.capacity()
+    }
+
+    /// Returns what `send` does when the channel is full.
+    pub fn overflow(&self) -> Overflow {
+        self.sender.overflow()
+    }
 }
 
+// batching of outputs which do not need to set it, i.e. prometheus
+const DEFAULT_BATCH_SIZE: usize = 1000;
+const DEFAULT_INTERVAL: usize = 5;
+
 #[derive(Debug, Deserialize)]
 pub struct PublisherConfig {
-    log_name: String,
-    batch_size: usize,
-    interval: usize,
+    // required by every output but prometheus, see `OutputConfig::validate`
+    log_name: Option<String>,
+    batch_size: Option<usize>,
+    interval: Option<usize>,
+    #[serde(default)]
+    retry: RetryConfig,
+    spool: Option<SpoolConfig>,
+    #[serde(default)]
+    channel: ChannelConfig,
+    #[serde(default = "default_shutdown_timeout")]
+    shutdown_timeout: u64,
+}
+
+fn default_shutdown_timeout() -> u64 {
+    30
 }
 
 impl PublisherConfig {
     pub fn new(log_name: impl Into<String>, batch_size: usize, interval: usize) -> Self {
         Self {
-            log_name: log_name.into(),
-            batch_size,
-            interval,
+            log_name: Some(log_name.into()),
+            batch_size: Some(batch_size),
+            interval: Some(interval),
+            retry: RetryConfig::default(),
+            spool: None,
+            channel: ChannelConfig::default(),
+            shutdown_timeout: default_shutdown_timeout(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_spool(mut self, spool: SpoolConfig) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    pub fn without_spool(mut self) -> Self {
+        self.spool = None;
+        self
+    }
+
+    pub fn spool(&self) -> Option<&SpoolConfig> {
+        self.spool.as_ref()
+    }
+
+    /// Returns `true` when `log_name`, `batch_size` and `interval` are set.
+    pub fn has_batching(&self) -> bool {
+        self.log_name.is_some() && self.batch_size.is_some() && self.interval.is_some()
+    }
+
+    /// Fails on settings the publisher cannot run with.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.interval == Some(0) {
+            return Err(ConfigError::Message(
+                "output interval must be at least 1 second".to_string(),
+            ));
         }
+
+        Ok(())
+    }
+
+    pub fn with_channel(mut self, channel: ChannelConfig) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// Sets how many seconds the final flush may take on shutdown.
+    pub fn with_shutdown_timeout(mut self, shutdown_timeout: u64) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
     }
 
-    pub fn into_parts(self) -> (String, usize, Duration) {
+    #[allow(clippy::type_complexity)]
+    pub fn into_parts(
+        self,
+    ) -> (
+        String,
+        usize,
+        Duration,
+        RetryConfig,
+        Option<SpoolConfig>,
+        ChannelConfig,
+        Duration,
+    ) {
         (
-            self.log_name,
-            self.batch_size,
-            Duration::from_secs(self.interval as u64),
+            self.log_name.unwrap_or_default(),
+            self.batch_size.unwrap_or(DEFAULT_BATCH_SIZE),
+            Duration::from_secs(self.interval.unwrap_or(DEFAULT_INTERVAL) as u64),
+            self.retry,
+            self.spool,
+            self.channel,
+            Duration::from_secs(self.shutdown_timeout),
         )
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use futures_util::future::BoxFuture;
+
+    use hyper::StatusCode;
+
     use super::*;
-    use crate::ClientConfig;
+    use crate::{
+        mock::{MockServer, Reply},
+        Client, MemorySink, ResponseError,
+    };
+
+    struct UnavailableSink;
+
+    impl Sink for UnavailableSink {
+        fn send<'a>(&'a self, _: &'a str, _: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+            futures_util::future::err(anyhow::anyhow!("connection refused")).boxed()
+        }
+    }
 
     #[tokio::test]
     async fn it_publishes_data_from_channel() {
         let _ = env_logger::builder().is_test(true).try_init();
 
-        let customer_id = "";
-        let shared_key = "";
+        let server = MockServer::start();
+        let client = Client::new(server.client_config()).unwrap();
 
-        let config = ClientConfig::new(customer_id, shared_key);
-        let client = Client::new(config).unwrap();
-
-        let config = PublisherConfig::new("StatEntries", 10, 2);
-        let (publisher, publisher_handle) = Publisher::new(client, config);
+        let config =
+            PublisherConfig::new("StatEntries", 10, 2).with_retry(RetryConfig::new(1, 0, 0, 0));
+        let (publisher, publisher_handle) = Publisher::new(client, config).unwrap();
         let task = tokio::spawn(publisher.run());
 
         let data = serde_json::json!(
             {"DemoField1":"DemoValue1","DemoField2":"DemoValue2"}
         );
 
-        publisher_handle.send(data.clone());
-        publisher_handle.send(data.clone());
-        publisher_handle.send(data.clone());
-        publisher_handle.send(data.clone());
-        publisher_handle.send(data.clone());
-        publisher_handle.send(data.clone());
-        publisher_handle.send(data);
+        publisher_handle.send(data.clone()).await;
+        publisher_handle.send(data.clone()).await;
+        publisher_handle.send(data.clone()).await;
+        publisher_handle.send(data.clone()).await;
+        publisher_handle.send(data.clone()).await;
+        publisher_handle.send(data.clone()).await;
+        publisher_handle.send(data.clone()).await;
+
+        drop(publisher_handle);
+
+        task.await.unwrap();
+
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].log_type, "StatEntries");
+        assert_eq!(received[0].payload, Value::from(vec![data; 7]));
+    }
+
+    #[tokio::test]
+    async fn it_retries_failed_batches() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let server = MockServer::start();
+        server.script(vec![
+            Reply::Status(StatusCode::INTERNAL_SERVER_ERROR),
+            Reply::RetryAfter(StatusCode::TOO_MANY_REQUESTS, 0),
+        ]);
+        let client = Client::new(server.client_config()).unwrap();
 
+        let config =
+            PublisherConfig::new("StatEntries", 2, 2).with_retry(RetryConfig::new(3, 60, 0, 0));
+        let (publisher, publisher_handle) = Publisher::new(client, config).unwrap();
+        let task = tokio::spawn(publisher.run());
+
+        publisher_handle.send(serde_json::json!({"batch": 1})).await;
+        publisher_handle.send(serde_json::json!({"batch": 1})).await;
         drop(publisher_handle);
+        task.await.unwrap();
 
+        let statuses = server
+            .received()
+            .into_iter()
+            .map(|received| received.status)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            vec![
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::OK
+            ]
+        );
+        assert_eq!(server.accepted().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_drops_rejected_batches() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        let server = MockServer::start();
+        server.script(vec![Reply::Status(StatusCode::BAD_REQUEST)]);
+        let client = Client::new(server.client_config()).unwrap();
+
+        let config =
+            PublisherConfig::new("StatEntries", 1, 2).with_retry(RetryConfig::new(1, 0, 0, 0));
+        let (publisher, publisher_handle) = Publisher::new(client, config).unwrap();
+        let task = tokio::spawn(publisher.run());
+
+        publisher_handle.send(serde_json::json!({"batch": 1})).await;
+        publisher_handle.send(serde_json::json!({"batch": 2})).await;
+        drop(publisher_handle);
         task.await.unwrap();
+
+        assert_eq!(server.received().len(), 2);
+        assert_eq!(
+            server.accepted(),
+            vec![Value::from(vec![serde_json::json!({"batch": 2})])]
+        );
+    }
+
+    // fails the first `failures` sends with a 503 asking to retry after `retry_after`
+    struct FlakySink {
+        failures: std::sync::atomic::AtomicUsize,
+        retry_after: Duration,
+        sink: MemorySink,
+    }
+
+    impl Sink for FlakySink {
+        fn send<'a>(&'a self, log_name: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+            use std::sync::atomic::Ordering;
+
+            let failed = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |failures| {
+                    failures.checked_sub(1)
+                })
+                .is_ok();
+            if failed {
+                let e =
+                    ResponseError::new(StatusCode::SERVICE_UNAVAILABLE, Some(self.retry_after), "");
+                return futures_util::future::err(e.into()).boxed();
+            }
+            self.sink.send(log_name, items)
+        }
+    }
+
+    #[tokio::test]
+    async fn it_bounds_parked_items_while_a_batch_waits_for_retry() {
+        time::pause();
+        let sink = MemorySink::new();
+        let flaky = FlakySink {
+            failures: 1.into(),
+            retry_after: Duration::from_secs(30),
+            sink: sink.clone(),
+        };
+        let config = PublisherConfig::new("StatEntries", 1, 1)
+            .with_channel(ChannelConfig::new(2, channel::Overflow::DropOldest));
+        let (publisher, publisher_handle) = Publisher::new(flaky, config).unwrap();
+        tokio::spawn(publisher.run());
+
+        for batch in 0..6 {
+            publisher_handle
+                .send(serde_json::json!({ "batch": batch }))
+                .await;
+            sleep(1000).await;
+        }
+        assert!(sink.items().is_empty());
+
+        // two items are parked and two wait in the channel, which dropped the
+        // oldest of them to make room for the last two
+        sleep(30_000).await;
+        assert_eq!(publisher_handle.dropped(), 2);
+        assert_eq!(
+            sink.items(),
+            [0, 1, 4, 5]
+                .iter()
+                .map(|batch| serde_json::json!({ "batch": batch }))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    struct HangingSink;
+
+    impl Sink for HangingSink {
+        fn send<'a>(&'a self, _: &'a str, _: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+            futures_util::future::pending().boxed()
+        }
+    }
+
+    // with paused time the clock jumps to the next timer whenever the runtime
+    // is idle, so sleeping moves the test forward deterministically
+    async fn sleep(millis: u64) {
+        time::delay_for(Duration::from_millis(millis)).await;
+    }
+
+    fn batch_sizes(sink: &MemorySink) -> Vec<usize> {
+        sink.batches()
+            .into_iter()
+            .map(|(_, items)| items.len())
+            .collect()
+    }
+
+    #[test]
+    fn it_rejects_zero_interval() {
+        let config = PublisherConfig::new("StatEntries", 10, 0);
+        assert!(Publisher::<Value>::new(MemorySink::new(), config).is_err());
+    }
+
+    #[tokio::test]
+    async fn it_flushes_on_fixed_interval_under_steady_traffic() {
+        time::pause();
+        let sink = MemorySink::new();
+        let config = PublisherConfig::new("StatEntries", 100, 10);
+        let (publisher, publisher_handle) = Publisher::new(sink.clone(), config).unwrap();
+        tokio::spawn(publisher.run());
+
+        // an item every 3 seconds neither fills a batch nor delays the ticker
+        for _ in 0..7 {
+            publisher_handle.send(serde_json::json!({})).await;
+            sleep(3000).await;
+        }
+
+        assert_eq!(batch_sizes(&sink), vec![4, 3]);
+    }
+
+    #[tokio::test]
+    async fn it_flushes_full_batches_without_waiting_for_interval() {
+        time::pause();
+        let sink = MemorySink::new();
+        let config = PublisherConfig::new("StatEntries", 2, 10);
+        let (publisher, publisher_handle) = Publisher::new(sink.clone(), config).unwrap();
+        tokio::spawn(publisher.run());
+
+        sleep(7000).await;
+        for _ in 0..3 {
+            publisher_handle.send(serde_json::json!({})).await;
+        }
+        sleep(1).await;
+        assert_eq!(batch_sizes(&sink), vec![2]);
+
+        // the size triggered flush at 7s does not postpone the tick at 10s
+        sleep(3000).await;
+        assert_eq!(batch_sizes(&sink), vec![2, 1]);
+    }
+
+    #[tokio::test]
+    async fn it_flushes_queued_items_on_stop() {
+        let sink = MemorySink::new();
+        let config = PublisherConfig::new("StatEntries", 2, 60);
+        let (publisher, publisher_handle) = Publisher::new(sink.clone(), config).unwrap();
+        let task = tokio::spawn(publisher.run());
+
+        for batch in 0..5 {
+            publisher_handle
+                .send(serde_json::json!({ "batch": batch }))
+                .await;
+        }
+        publisher_handle.stop();
+
+        // the handle is still alive, the stop alone ends the publisher
+        let lost = time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lost, 0);
+        assert_eq!(sink.items().len(), 5);
+        drop(publisher_handle);
+    }
+
+    #[tokio::test]
+    async fn it_reports_items_lost_after_shutdown_timeout() {
+        let config = PublisherConfig::new("StatEntries", 2, 60).with_shutdown_timeout(0);
+        let (publisher, publisher_handle) = Publisher::new(HangingSink, config).unwrap();
+        let task = tokio::spawn(publisher.run());
+
+        for batch in 0..3 {
+            publisher_handle
+                .send(serde_json::json!({ "batch": batch }))
+                .await;
+        }
+        publisher_handle.stop();
+
+        let lost = time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lost, 3);
+    }
+
+    // accepts batches up to 28 bytes with fields up to 4 bytes
+    struct LimitedSink(MemorySink);
+
+    impl Sink for LimitedSink {
+        fn send<'a>(&'a self, log_name: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+            self.0.send(log_name, items)
+        }
+
+        fn limits(&self) -> Limits {
+            Limits {
+                max_batch_size: Some(28),
+                max_field_size: Some(4),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn it_fits_batches_to_sink_limits() {
+        let sink = MemorySink::new();
+        let config = PublisherConfig::new("StatEntries", 10, 60);
+        let (publisher, publisher_handle) =
+            Publisher::new(LimitedSink(sink.clone()), config).unwrap();
+        let task = tokio::spawn(publisher.run());
+
+        for id in 0..4 {
+            publisher_handle.send(serde_json::json!({ "id": id })).await;
+        }
+        publisher_handle
+            .send(serde_json::json!({ "id": "truncated" }))
+            .await;
+        publisher_handle.stop();
+        assert_eq!(task.await.unwrap(), 0);
+
+        assert_eq!(batch_sizes(&sink), vec![3, 2]);
+        assert_eq!(sink.items()[4], serde_json::json!({ "id": "trun" }));
+        assert_eq!(publisher_handle.split(), 5);
+        assert_eq!(publisher_handle.truncated(), 1);
+    }
+
+    struct RejectingSink;
+
+    impl Sink for RejectingSink {
+        fn send<'a>(&'a self, _: &'a str, _: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+            let e = ResponseError::new(StatusCode::BAD_REQUEST, None, "invalid record");
+            futures_util::future::err(e.into()).boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn it_reports_items_rejected_on_stop_as_lost() {
+        let config = PublisherConfig::new("StatEntries", 2, 60);
+        let (publisher, publisher_handle) = Publisher::new(RejectingSink, config).unwrap();
+        let task = tokio::spawn(publisher.run());
+
+        for batch in 0..3 {
+            publisher_handle
+                .send(serde_json::json!({ "batch": batch }))
+                .await;
+        }
+        publisher_handle.stop();
+
+        let lost = time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lost, 3);
+    }
+
+    #[tokio::test]
+    async fn it_spools_items_left_after_shutdown_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = SpoolConfig::new(dir.path(), 1024 * 1024, 3600);
+        let config = PublisherConfig::new("StatEntries", 2, 60)
+            .with_spool(spool.clone())
+            .with_shutdown_timeout(0);
+        let (publisher, publisher_handle) = Publisher::new(HangingSink, config).unwrap();
+        let task = tokio::spawn(publisher.run());
+
+        for batch in 0..3 {
+            publisher_handle
+                .send(serde_json::json!({ "batch": batch }))
+                .await;
+        }
+        publisher_handle.stop();
+
+        let lost = time::timeout(Duration::from_secs(5), task)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lost, 0);
+        assert_eq!(Spool::open(spool).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn it_replays_spooled_data_after_restart() {
+        let _ = env_logger::builder().is_test(true).try_init();
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = || {
+            PublisherConfig::new("StatEntries", 10, 2).with_spool(SpoolConfig::new(
+                dir.path(),
+                1024 * 1024,
+                3600,
+            ))
+        };
+        let data = serde_json::json!({"DemoField1":"DemoValue1"});
+
+        let (publisher, publisher_handle) = Publisher::new(UnavailableSink, config()).unwrap();
+        let task = tokio::spawn(publisher.run());
+        publisher_handle.send(data.clone()).await;
+        drop(publisher_handle);
+        task.await.unwrap();
+
+        let sink = MemorySink::new();
+        let (publisher, publisher_handle) =
+            Publisher::<Value>::new(sink.clone(), config()).unwrap();
+        drop(publisher_handle);
+        publisher.run().await;
+
+        assert_eq!(sink.items(), vec![data]);
     }
 }
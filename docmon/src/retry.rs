@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::{backoff::Backoff, client::ResponseError};
+
+/// What to do with a batch after a failed send attempt.
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Try again after the given delay.
+    Retry(Duration),
+    /// The batch was rejected and will never be accepted.
+    Reject,
+    /// Retry limits are exhausted.
+    GiveUp,
+}
+
+/// Whether a failed send may succeed when retried.
+#[derive(Debug, PartialEq)]
+pub enum Failure {
+    /// Retry, not earlier than the delay requested by the server if any.
+    Retryable(Option<Duration>),
+    /// Sending the same data again will fail again.
+    Permanent,
+}
+
+pub fn classify(error: &anyhow::Error) -> Failure {
+    match error.downcast_ref::<ResponseError>() {
+        Some(response) if !response.is_retryable() => Failure::Permanent,
+        Some(response) => Failure::Retryable(response.retry_after()),
+        None if error.is::<serde_json::Error>() => Failure::Permanent,
+        None => Failure::Retryable(None),
+    }
+}
+
+/// Tracks send attempts of a single batch.
+#[derive(Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    max_age: Duration,
+    backoff: Backoff,
+    started: Instant,
+    attempts: u32,
+}
+
+impl RetryPolicy {
+    pub fn new(config: &RetryConfig) -> Self {
+        Self {
+            max_attempts: config.max_attempts,
+            max_age: Duration::from_secs(config.max_age as u64),
+            backoff: config.backoff(),
+            started: Instant::now(),
+            attempts: 0,
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Returns `true` once the batch is older than `max_age`.
+    pub fn is_expired(&self) -> bool {
+        self.started.elapsed() >= self.max_age
+    }
+
+    pub fn on_error(&mut self, error: &anyhow::Error) -> Decision {
+        self.attempts += 1;
+
+        let retry_after = match classify(error) {
+            Failure::Permanent => return Decision::Reject,
+            Failure::Retryable(retry_after) => retry_after,
+        };
+
+        let elapsed = self.started.elapsed();
+        if self.attempts >= self.max_attempts || elapsed >= self.max_age {
+            return Decision::GiveUp;
+        }
+
+        // the last attempt is made when the batch reaches its age limit
+        let delay = retry_after.unwrap_or_else(|| self.backoff.next_delay());
+        Decision::Retry(delay.min(self.max_age - elapsed))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    max_attempts: u32,
+    max_age: usize,
+    initial_backoff: usize,
+    max_backoff: usize,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            max_age: 3600,
+            initial_backoff: 1,
+            max_backoff: 300,
+        }
+    }
+}
+
+impl RetryConfig {
+    pub fn new(
+        max_attempts: u32,
+        max_age: usize,
+        initial_backoff: usize,
+        max_backoff: usize,
+    ) -> Self {
+        Self {
+            max_attempts,
+            max_age,
+            initial_backoff,
+            max_backoff,
+        }
+    }
+
+    pub(crate) fn backoff(&self) -> Backoff {
+        Backoff::new(
+            Duration::from_secs(self.initial_backoff as u64),
+            Duration::from_secs(self.max_backoff as u64),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_error(status: u16, retry_after: Option<u64>) -> anyhow::Error {
+        ResponseError::new(
+            hyper::StatusCode::from_u16(status).unwrap(),
+            retry_after.map(Duration::from_secs),
+            "",
+        )
+        .into()
+    }
+
+    #[tokio::test]
+    async fn it_rejects_permanent_errors() {
+        let mut policy = RetryPolicy::new(&RetryConfig::default());
+
+        assert_eq!(
+            policy.on_error(&response_error(400, None)),
+            Decision::Reject
+        );
+        assert_eq!(
+            policy.on_error(&response_error(403, None)),
+            Decision::Reject
+        );
+    }
+
+    #[tokio::test]
+    async fn it_honors_retry_after() {
+        let mut policy = RetryPolicy::new(&RetryConfig::default());
+
+        assert_eq!(
+            policy.on_error(&response_error(429, Some(42))),
+            Decision::Retry(Duration::from_secs(42))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_clamps_retry_after_to_max_age() {
+        tokio::time::pause();
+        let mut policy = RetryPolicy::new(&RetryConfig::new(10, 60, 1, 1));
+
+        assert_eq!(
+            policy.on_error(&response_error(503, Some(3600))),
+            Decision::Retry(Duration::from_secs(60))
+        );
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_after_max_attempts() {
+        let mut policy = RetryPolicy::new(&RetryConfig::new(3, 3600, 1, 1));
+
+        assert!(matches!(
+            policy.on_error(&anyhow::anyhow!("connection refused")),
+            Decision::Retry(_)
+        ));
+        assert!(matches!(
+            policy.on_error(&response_error(500, None)),
+            Decision::Retry(_)
+        ));
+        assert_eq!(
+            policy.on_error(&response_error(503, None)),
+            Decision::GiveUp
+        );
+        assert_eq!(policy.attempts(), 3);
+    }
+
+    #[tokio::test]
+    async fn it_gives_up_after_max_age() {
+        let mut policy = RetryPolicy::new(&RetryConfig::new(10, 0, 1, 1));
+
+        assert_eq!(
+            policy.on_error(&response_error(500, None)),
+            Decision::GiveUp
+        );
+    }
+}
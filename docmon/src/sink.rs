@@ -0,0 +1,197 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use futures_util::future::{BoxFuture, FutureExt};
+use serde_json::Value;
+
+/// A backend which accepts batches of records collected by `Publisher`.
+///
+/// Records are passed already serialized, so the same batch can be spooled to
+/// disk and replayed later. Errors are classified for retries the same way as
+/// for the Data Collector API: `ResponseError` with a non-retryable status and
+/// `serde_json::Error` reject the batch, anything else is retried.
+pub trait Sink: Send + Sync {
+    /// Sends a batch of records to the log (table, stream) called `log_name`.
+    fn send<'a>(&'a self, log_name: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>>;
+
+    /// Limits of a single `send`, which `Publisher` keeps batches within.
+    fn limits(&self) -> Limits {
+        Limits::default()
+    }
+}
+
+/// Size limits of a sink, in bytes of serialized JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    /// The largest batch, serialized as a JSON array.
+    pub max_batch_size: Option<usize>,
+    /// The largest value of a top level field, longer ones are truncated.
+    pub max_field_size: Option<usize>,
+}
+
+impl Limits {
+    /// Truncates fields over `max_field_size`, returns `true` if any was.
+    pub fn truncate(&self, record: &mut Value) -> bool {
+        match self.max_field_size {
+            Some(max_field_size) => truncate_fields(record, max_field_size),
+            None => false,
+        }
+    }
+
+    /// Splits records into batches of at most `max_batch_size`.
+    pub fn split(&self, records: Vec<Value>) -> Vec<Vec<Value>> {
+        match self.max_batch_size {
+            Some(max_batch_size) => split(records, max_batch_size),
+            None => vec![records],
+        }
+    }
+}
+
+// a record which does not fit alone is sent alone and left to the service
+fn split(records: Vec<Value>, max_batch_size: usize) -> Vec<Vec<Value>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    // the brackets of the array
+    let mut size = 2;
+    for record in records {
+        let len = serde_json::to_vec(&record).map_or(0, |data| data.len());
+        // and a separating comma
+        if !batch.is_empty() && size + len + 1 > max_batch_size {
+            batches.push(std::mem::take(&mut batch));
+            size = 2;
+        }
+        if !batch.is_empty() {
+            size += 1;
+        }
+        size += len;
+        batch.push(record);
+    }
+    if !batch.is_empty() || batches.is_empty() {
+        batches.push(batch);
+    }
+
+    batches
+}
+
+// fields are stored as strings, so nested values over the limit are
+// serialized and truncated like strings
+fn truncate_fields(record: &mut Value, max_field_size: usize) -> bool {
+    let fields = match record.as_object_mut() {
+        Some(fields) => fields,
+        None => return false,
+    };
+
+    let mut truncated = false;
+    for value in fields.values_mut() {
+        let mut text = match value {
+            Value::String(text) if text.len() > max_field_size => std::mem::take(text),
+            Value::Array(_) | Value::Object(_) => match serde_json::to_string(value) {
+                Ok(text) if text.len() > max_field_size => text,
+                _ => continue,
+            },
+            _ => continue,
+        };
+
+        let mut end = max_field_size;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        *value = Value::String(text);
+        truncated = true;
+    }
+
+    truncated
+}
+
+impl<S> Sink for Box<S>
+where
+    S: Sink + ?Sized,
+{
+    fn send<'a>(&'a self, log_name: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        (**self).send(log_name, items)
+    }
+
+    fn limits(&self) -> Limits {
+        (**self).limits()
+    }
+}
+
+type Batch = (String, Vec<Value>);
+
+/// Keeps every batch in memory. Clones share the same storage.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    batches: Arc<Mutex<Vec<Batch>>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns all batches received so far along with their log names.
+    pub fn batches(&self) -> Vec<Batch> {
+        self.batches.lock().expect("sink lock poisoned").clone()
+    }
+
+    /// Returns all records received so far.
+    pub fn items(&self) -> Vec<Value> {
+        self.batches()
+            .into_iter()
+            .flat_map(|(_, items)| items)
+            .collect()
+    }
+}
+
+impl Sink for MemorySink {
+    fn send<'a>(&'a self, log_name: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        self.batches
+            .lock()
+            .expect("sink lock poisoned")
+            .push((log_name.to_string(), items.to_vec()));
+
+        futures_util::future::ok(()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn it_splits_records_by_batch_size() {
+        let records = (0..5).map(|i| json!({ "id": i })).collect::<Vec<_>>();
+
+        // 8 bytes per record, 3 of them with brackets and commas take 28
+        let batches = split(records.clone(), 28);
+
+        assert_eq!(batches, vec![records[..3].to_vec(), records[3..].to_vec()]);
+        assert_eq!(split(Vec::new(), 28), vec![Vec::<Value>::new()]);
+        assert_eq!(Limits::default().split(records.clone()), vec![records]);
+    }
+
+    #[test]
+    fn it_truncates_oversized_fields() {
+        let mut record = json!({
+            "name": "web",
+            "image": "ä".repeat(6),
+            "labels": { "tier": "front" },
+            "memory": 1024,
+        });
+
+        assert!(truncate_fields(&mut record, 11));
+        assert_eq!(
+            record,
+            json!({
+                "name": "web",
+                "image": "ä".repeat(5),
+                "labels": r#"{"tier":"fr"#,
+                "memory": 1024,
+            })
+        );
+        assert!(!truncate_fields(&mut record, 11));
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const SEGMENT_EXTENSION: &str = "json";
+const TMP_EXTENSION: &str = "tmp";
+
+/// An on-disk queue of batches which have not been delivered yet.
+///
+/// Every batch is stored in its own segment file named after a monotonically
+/// increasing sequence number, so batches are replayed in the order they were
+/// written, including after a restart. Segments are evicted oldest first when
+/// the spool grows above `max_size` bytes or a segment gets older than `max_age`.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    max_size: u64,
+    max_age: Duration,
+    next_seq: u64,
+    size: u64,
+    segments: VecDeque<Segment>,
+}
+
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    path: PathBuf,
+    size: u64,
+    created: SystemTime,
+}
+
+impl Spool {
+    pub fn open(config: SpoolConfig) -> Result<Self> {
+        let (dir, max_size, max_age) = config.into_parts();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("unable to create spool directory {}", dir.display()))?;
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            // left behind by a crash before the segment was complete
+            if path.extension().and_then(|ext| ext.to_str()) == Some(TMP_EXTENSION) {
+                warn!("removing incomplete spool segment {}", path.display());
+                fs::remove_file(&path)?;
+                continue;
+            }
+            if let Some(segment) = Segment::load(&path)? {
+                segments.push(segment);
+            }
+        }
+        segments.sort_by_key(|segment| segment.seq);
+
+        let next_seq = segments.last().map_or(0, |segment| segment.seq + 1);
+        let size = segments.iter().map(|segment| segment.size).sum();
+
+        let mut spool = Self {
+            dir,
+            max_size,
+            max_age,
+            next_seq,
+            size,
+            segments: segments.into(),
+        };
+
+        if !spool.is_empty() {
+            info!(
+                "found {} spooled batch(es) in {}",
+                spool.len(),
+                spool.dir.display()
+            );
+        }
+        spool.evict();
+
+        Ok(spool)
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Writes a batch to a new segment.
+    pub fn push<I>(&mut self, items: &I) -> Result<()>
+    where
+        I: Serialize,
+    {
+        let data = serde_json::to_vec(items)?;
+        let seq = self.next_seq;
+        let path = self.dir.join(format!("{:020}.{}", seq, SEGMENT_EXTENSION));
+
+        // a partially written segment must never be replayed, so the data is
+        // on disk before the rename and the rename before the segment counts
+        let tmp = path.with_extension(TMP_EXTENSION);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &path)?;
+        File::open(&self.dir)?.sync_all()?;
+
+        debug!("spooled batch {} ({} bytes)", seq, data.len());
+        self.next_seq += 1;
+        self.size += data.len() as u64;
+        self.segments.push_back(Segment {
+            seq,
+            path,
+            size: data.len() as u64,
+            created: SystemTime::now(),
+        });
+
+        self.evict_oversized();
+        Ok(())
+    }
+
+    /// Reads the oldest batch.
+    pub fn front(&self) -> Option<Result<Vec<Value>>> {
+        self.segments.front().map(|segment| {
+            let data = fs::read(&segment.path)?;
+            Ok(serde_json::from_slice(&data)?)
+        })
+    }
+
+    /// Removes the oldest batch.
+    pub fn pop(&mut self) {
+        if let Some(segment) = self.segments.pop_front() {
+            self.remove(segment);
+        }
+    }
+
+    /// Removes segments above the size and age caps and returns how many were dropped.
+    pub fn evict(&mut self) -> usize {
+        let now = SystemTime::now();
+        let max_age = self.max_age;
+
+        self.evict_while(|spool, segment| {
+            spool.size > spool.max_size
+                || now
+                    .duration_since(segment.created)
+                    .is_ok_and(|age| age > max_age)
+        })
+    }
+
+    fn evict_oversized(&mut self) -> usize {
+        self.evict_while(|spool, _| spool.size > spool.max_size)
+    }
+
+    fn evict_while(&mut self, predicate: impl Fn(&Self, &Segment) -> bool) -> usize {
+        let mut evicted = 0;
+
+        while let Some(segment) = self.segments.front() {
+            if !predicate(self, segment) {
+                break;
+            }
+
+            if let Some(segment) = self.segments.pop_front() {
+                self.remove(segment);
+                evicted += 1;
+            }
+        }
+
+        if evicted > 0 {
+            warn!(
+                "evicted {} spooled batch(es) over size or age limit",
+                evicted
+            );
+        }
+
+        evicted
+    }
+
+    fn remove(&mut self, segment: Segment) {
+        self.size = self.size.saturating_sub(segment.size);
+        if let Err(e) = fs::remove_file(&segment.path) {
+            warn!(
+                "unable to remove spool segment {}. {:?}",
+                segment.path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// A `Spool` used from async code. Segments are synced to disk, so every
+/// operation runs on the blocking thread pool.
+#[derive(Debug, Clone)]
+pub struct AsyncSpool(Arc<Mutex<Spool>>);
+
+impl AsyncSpool {
+    pub fn new(spool: Spool) -> Self {
+        Self(Arc::new(Mutex::new(spool)))
+    }
+
+    /// Runs `f` with the spool on the blocking thread pool. An interrupted
+    /// caller does not interrupt `f`, which always completes.
+    pub async fn run<F, T>(&self, f: F) -> T
+    where
+        F: FnOnce(&mut Spool) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let spool = self.0.clone();
+        tokio::task::spawn_blocking(move || f(&mut spool.lock().expect("spool lock poisoned")))
+            .await
+            .expect("spool task panicked")
+    }
+}
+
+impl Segment {
+    fn load(path: &Path) -> Result<Option<Self>> {
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXTENSION) {
+            return Ok(None);
+        }
+
+        let seq = match path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        {
+            Some(seq) => seq,
+            None => return Ok(None),
+        };
+
+        let metadata = fs::metadata(path)?;
+        Ok(Some(Self {
+            seq,
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            created: metadata.modified()?,
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SpoolConfig {
+    path: PathBuf,
+    max_size: u64,
+    max_age: u64,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("/var/lib/docmon/spool"),
+            max_size: 512 * 1024 * 1024,
+            max_age: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl SpoolConfig {
+    pub fn new(path: impl Into<PathBuf>, max_size: u64, max_age: u64) -> Self {
+        Self {
+            path: path.into(),
+            max_size,
+            max_age,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn into_parts(self) -> (PathBuf, u64, Duration) {
+        (self.path, self.max_size, Duration::from_secs(self.max_age))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn it_replays_batches_in_order_after_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SpoolConfig::new(dir.path(), 1024 * 1024, 3600);
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        spool.push(&vec![json!({"batch": 1})]).unwrap();
+        spool.push(&vec![json!({"batch": 2})]).unwrap();
+        drop(spool);
+
+        let mut spool = Spool::open(config).unwrap();
+        spool.push(&vec![json!({"batch": 3})]).unwrap();
+        assert_eq!(spool.len(), 3);
+
+        let mut batches = Vec::new();
+        while let Some(batch) = spool.front() {
+            batches.push(batch.unwrap());
+            spool.pop();
+        }
+
+        assert_eq!(
+            batches,
+            vec![
+                vec![json!({"batch": 1})],
+                vec![json!({"batch": 2})],
+                vec![json!({"batch": 3})]
+            ]
+        );
+        assert!(spool.is_empty());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn it_removes_incomplete_segments_on_open() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = SpoolConfig::new(dir.path(), 1024 * 1024, 3600);
+
+        let mut spool = Spool::open(config.clone()).unwrap();
+        spool.push(&vec![json!({"batch": 1})]).unwrap();
+        drop(spool);
+        fs::write(dir.path().join("00000000000000000001.tmp"), b"[{\"bat").unwrap();
+
+        let spool = Spool::open(config).unwrap();
+        assert_eq!(spool.len(), 1);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn it_evicts_oldest_batches_over_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let batch = vec![json!({"field": "value"})];
+        let batch_size = serde_json::to_vec(&batch).unwrap().len() as u64;
+
+        let mut spool = Spool::open(SpoolConfig::new(dir.path(), batch_size * 2, 3600)).unwrap();
+        spool.push(&vec![json!({"field": "first"})]).unwrap();
+        spool.push(&vec![json!({"field": "secnd"})]).unwrap();
+        spool.push(&vec![json!({"field": "third"})]).unwrap();
+
+        assert_eq!(spool.len(), 2);
+        assert_eq!(
+            spool.front().unwrap().unwrap(),
+            vec![json!({"field": "secnd"})]
+        );
+    }
+
+    #[test]
+    fn it_evicts_expired_batches() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut spool = Spool::open(SpoolConfig::new(dir.path(), 1024 * 1024, 0)).unwrap();
+        spool.push(&vec![json!({"field": "value"})]).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+
+        assert_eq!(spool.evict(), 1);
+        assert!(spool.is_empty());
+    }
+}
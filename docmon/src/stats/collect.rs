@@ -1,33 +1,68 @@
-use std::{collections::HashMap, future::Future, time::Duration};
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
 
-use bollard::{container::ListContainersOptions, Docker};
+use anyhow::Result;
+use bollard::{
+    container::{InspectContainerOptions, ListContainersOptions},
+    system::{EventsOptions, EventsResults},
+    Docker,
+};
+use config::ConfigError;
 use futures_util::{
     future::{self, FutureExt},
-    select,
+    pin_mut, select, StreamExt,
 };
 use log::{debug, error, info, warn};
+use serde::Deserialize;
 use tokio::{
-    sync::oneshot::{self, Sender},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot::{self, Sender},
+    },
     task::JoinHandle,
     time,
 };
 
-use super::emit::Emitter;
-use crate::{PublisherHandle, Stats};
+use super::{
+    emit::Emitter,
+    filter::{ContainerInfo, Filter, FilterConfig},
+    metadata::{EngineInfo, MetadataConfig, MetadataResolver},
+    route::Router,
+    sample::SamplingConfig,
+};
 
 pub struct Collector {
     docker: Docker,
-    publisher_handle: PublisherHandle<Stats>,
+    router: Arc<Router>,
     containers: HashMap<String, (Sender<()>, JoinHandle<()>)>,
+    reconcile_interval: Duration,
+    filter: Filter,
+    metadata: Arc<MetadataResolver>,
+    sampling: SamplingConfig,
+    docker_socket: String,
+    terminated_sender: UnboundedSender<String>,
+    terminated_receiver: UnboundedReceiver<String>,
 }
 
 impl Collector {
-    pub fn new(docker: Docker, handle: PublisherHandle<Stats>) -> Self {
-        Self {
+    pub fn new(docker: Docker, router: impl Into<Router>, config: CollectorConfig) -> Result<Self> {
+        config.validate()?;
+        let (docker_socket, reconcile_interval, filter, metadata, sampling) = config.into_parts();
+        let filter = Filter::new(filter)?;
+        let metadata = Arc::new(MetadataResolver::new(metadata));
+        let (terminated_sender, terminated_receiver) = mpsc::unbounded_channel();
+
+        Ok(Self {
             docker,
-            publisher_handle: handle,
+            router: Arc::new(router.into()),
             containers: HashMap::new(),
-        }
+            reconcile_interval,
+            filter,
+            metadata,
+            sampling,
+            docker_socket,
+            terminated_sender,
+            terminated_receiver,
+        })
     }
 
     pub async fn run<F>(mut self, shutdown_signal: F)
@@ -47,7 +82,7 @@ impl Collector {
 
         info!("shutting down all stats emitters");
         let (shutdown_handles, join_handles): (Vec<_>, Vec<_>) =
-            self.containers.into_iter().map(|(_, x)| x).unzip();
+            self.containers.into_values().unzip();
 
         for shutdown_handle in shutdown_handles {
             if let Err(e) = shutdown_handle.send(()) {
@@ -64,55 +99,435 @@ impl Collector {
     }
 
     async fn collect(&mut self) {
+        if self.metadata.requires_engine() {
+            match EngineInfo::load(&self.docker_socket).await {
+                Ok(engine) => Arc::make_mut(&mut self.metadata).set_engine(engine),
+                Err(e) => warn!("unable to get docker engine info. {:?}", e),
+            }
+        }
+
+        let mut reconcile = time::interval_at(
+            time::Instant::now() + self.reconcile_interval,
+            self.reconcile_interval,
+        );
+
         loop {
-            let options = ListContainersOptions::<String>::default();
-            match self.docker.list_containers(Some(options)).await {
-                Ok(list) => {
-                    debug!("received a list of {} containers", list.len());
-                    // start stats emitter for each new container
-                    for container in &list {
-                        if !self.containers.contains_key(&container.id) {
-                            let (tx, rx) = oneshot::channel();
-                            let emitter = Emitter::new(
-                                container.id.clone(),
-                                self.docker.clone(),
-                                self.publisher_handle.clone(),
-                            );
-                            let join_handle = tokio::spawn(emitter.run(rx.map(drop)));
-
-                            self.containers
-                                .insert(container.id.clone(), (tx, join_handle));
-                        }
-                    }
+            info!("subscribing to docker events");
+            let events = self.docker.events(None::<EventsOptions<String>>).fuse();
+            pin_mut!(events);
+
+            // events could have been missed while the stream was not connected
+            self.reconcile().await;
 
-                    // stop stats emitter for old containers
-                    for container_id in self
-                        .containers
-                        .keys()
-                        .filter(|container_id| {
-                            !list.iter().any(|container| container.id == **container_id)
-                        })
-                        .cloned()
-                        .collect::<Vec<_>>()
-                    {
-                        info!("stopping stats emitter for {}", container_id);
-                        if let Some((shutdown_handle, join_handle)) =
-                            self.containers.remove(&container_id)
-                        {
-                            if let Err(e) = shutdown_handle.send(()) {
-                                warn!("error occurred when sending shutdown signal to stats emitter: {:?}", e);
-                            }
-
-                            if let Err(e) = join_handle.await {
-                                warn!("error occurred while stopping stats emitter: {:?}", e);
-                            }
+            loop {
+                select! {
+                    event = events.next() => match event {
+                        Some(Ok(event)) => self.handle_event(event).await,
+                        Some(Err(e)) => warn!("unable to read docker event. {:?}", e),
+                        None => {
+                            warn!("docker events stream closed");
+                            break;
                         }
+                    },
+                    container_id = self.terminated_receiver.next().fuse() => {
+                        if let Some(container_id) = container_id {
+                            self.remove_terminated_emitter(&container_id).await;
+                        }
+                    },
+                    _ = reconcile.tick().fuse() => {
+                        debug!("reconcile interval expired");
+                        self.reconcile().await;
                     }
                 }
-                Err(e) => error!("error occurred when containers list requested: {:?}", e),
             }
 
             time::delay_for(Duration::from_secs(1)).await;
         }
     }
+
+    async fn handle_event(&mut self, event: EventsResults) {
+        if event.type_ != "container" {
+            return;
+        }
+
+        debug!(
+            "received docker event {} for {}",
+            event.action, event.actor.id
+        );
+
+        match ContainerEvent::from_action(&event.action) {
+            Some(ContainerEvent::Started) => self.refresh(&event.actor.id).await,
+            Some(ContainerEvent::Changed) => {
+                // restart emitter to pick up new container metadata
+                self.stop_emitter(&event.actor.id).await;
+                self.refresh(&event.actor.id).await;
+            }
+            Some(ContainerEvent::Stopped) => self.stop_emitter(&event.actor.id).await,
+            None => {}
+        }
+    }
+
+    async fn reconcile(&mut self) {
+        let options = ListContainersOptions::<String>::default();
+        match self.docker.list_containers(Some(options)).await {
+            Ok(list) => {
+                debug!("received a list of {} containers", list.len());
+                let running = list
+                    .iter()
+                    .filter(|container| container.state != "paused")
+                    .map(ContainerInfo::from)
+                    .filter(|container| self.filter.matches(container))
+                    .map(|container| container.id)
+                    .collect::<Vec<_>>();
+
+                // start stats emitter for each new container
+                for container_id in &running {
+                    self.start_emitter(container_id);
+                }
+
+                // stop stats emitter for old containers
+                for container_id in self
+                    .containers
+                    .keys()
+                    .filter(|container_id| !running.contains(container_id))
+                    .cloned()
+                    .collect::<Vec<_>>()
+                {
+                    self.stop_emitter(&container_id).await;
+                }
+            }
+            Err(e) => error!("error occurred when containers list requested: {:?}", e),
+        }
+    }
+
+    // checks the filter rules again as container name or labels may have changed
+    async fn refresh(&mut self, container_id: &str) {
+        match self
+            .docker
+            .inspect_container(container_id, None::<InspectContainerOptions>)
+            .await
+        {
+            Ok(container) => {
+                let running = container.state.running && !container.state.paused;
+                if running && self.filter.matches(&ContainerInfo::from(&container)) {
+                    self.start_emitter(container_id);
+                } else {
+                    debug!("container {} does not match filter", container_id);
+                    self.stop_emitter(container_id).await;
+                }
+            }
+            Err(e) => error!(
+                "error occurred when container {} inspected: {:?}",
+                container_id, e
+            ),
+        }
+    }
+
+    fn start_emitter(&mut self, container_id: &str) {
+        if self.containers.contains_key(container_id) {
+            return;
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let emitter = Emitter::new(
+            container_id.to_string(),
+            self.docker.clone(),
+            self.router.clone(),
+            self.metadata.clone(),
+            self.sampling.clone(),
+            self.terminated_sender.clone(),
+        );
+        let join_handle = tokio::spawn(emitter.run(rx.map(drop)));
+
+        self.containers
+            .insert(container_id.to_string(), (tx, join_handle));
+    }
+
+    async fn remove_terminated_emitter(&mut self, container_id: &str) {
+        // the emitter could have been already replaced by a new one for a restarted container
+        let terminated = self
+            .containers
+            .get(container_id)
+            .is_some_and(|(shutdown_handle, _)| shutdown_handle.is_closed());
+
+        if terminated {
+            if let Some((_, join_handle)) = self.containers.remove(container_id) {
+                info!("removing terminated stats emitter for {}", container_id);
+                if let Err(e) = join_handle.await {
+                    warn!("error occurred while stopping stats emitter: {:?}", e);
+                }
+            }
+        }
+    }
+
+    async fn stop_emitter(&mut self, container_id: &str) {
+        if let Some((shutdown_handle, join_handle)) = self.containers.remove(container_id) {
+            info!("stopping stats emitter for {}", container_id);
+            if let Err(e) = shutdown_handle.send(()) {
+                warn!(
+                    "error occurred when sending shutdown signal to stats emitter: {:?}",
+                    e
+                );
+            }
+
+            if let Err(e) = join_handle.await {
+                warn!("error occurred while stopping stats emitter: {:?}", e);
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum ContainerEvent {
+    Started,
+    Changed,
+    Stopped,
+}
+
+impl ContainerEvent {
+    fn from_action(action: &str) -> Option<Self> {
+        match action {
+            "start" | "unpause" => Some(ContainerEvent::Started),
+            "rename" | "update" => Some(ContainerEvent::Changed),
+            "die" | "destroy" | "pause" => Some(ContainerEvent::Stopped),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CollectorConfig {
+    #[serde(default = "default_docker_socket")]
+    docker_socket: String,
+    #[serde(default = "default_reconcile_interval")]
+    reconcile_interval: usize,
+    #[serde(default)]
+    filter: FilterConfig,
+    #[serde(default)]
+    metadata: MetadataConfig,
+    #[serde(default)]
+    sampling: SamplingConfig,
+}
+
+fn default_docker_socket() -> String {
+    "unix:///var/run/docker.sock".to_string()
+}
+
+fn default_reconcile_interval() -> usize {
+    60
+}
+
+impl Default for CollectorConfig {
+    fn default() -> Self {
+        Self {
+            docker_socket: default_docker_socket(),
+            reconcile_interval: default_reconcile_interval(),
+            filter: FilterConfig::default(),
+            metadata: MetadataConfig::default(),
+            sampling: SamplingConfig::default(),
+        }
+    }
+}
+
+impl CollectorConfig {
+    pub fn new(
+        docker_socket: impl Into<String>,
+        reconcile_interval: usize,
+        filter: FilterConfig,
+        metadata: MetadataConfig,
+        sampling: SamplingConfig,
+    ) -> Self {
+        Self {
+            docker_socket: docker_socket.into(),
+            reconcile_interval,
+            filter,
+            metadata,
+            sampling,
+        }
+    }
+
+    pub fn docker_socket(&self) -> &str {
+        &self.docker_socket
+    }
+
+    /// Fails on settings the collector cannot run with.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.reconcile_interval == 0 {
+            return Err(ConfigError::Message(
+                "collector reconcile_interval must be at least 1 second".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn into_parts(
+        self,
+    ) -> (
+        String,
+        Duration,
+        FilterConfig,
+        MetadataConfig,
+        SamplingConfig,
+    ) {
+        (
+            self.docker_socket,
+            Duration::from_secs(self.reconcile_interval as u64),
+            self.filter,
+            self.metadata,
+            self.sampling,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        stats::fake::{FakeContainer, FakeDocker},
+        MemorySink, Publisher, PublisherConfig, RulesConfig,
+    };
+
+    struct Running {
+        sink: MemorySink,
+        shutdown: Sender<()>,
+        task: JoinHandle<()>,
+    }
+
+    fn start(docker: &FakeDocker, filter: FilterConfig) -> Running {
+        let sink = MemorySink::new();
+        let (publisher, handle) =
+            Publisher::new(sink.clone(), PublisherConfig::new("StatEntries", 1, 1)).unwrap();
+        tokio::spawn(publisher.run());
+
+        let config = CollectorConfig::new(
+            docker.socket(),
+            60,
+            filter,
+            MetadataConfig::default(),
+            SamplingConfig::default(),
+        );
+        let collector = Collector::new(docker.docker(), handle, config).unwrap();
+
+        let (shutdown, signal) = oneshot::channel();
+        let task = tokio::spawn(collector.run(signal.map(drop)));
+
+        Running {
+            sink,
+            shutdown,
+            task,
+        }
+    }
+
+    fn records(sink: &MemorySink, name: &str) -> Vec<Value> {
+        sink.items()
+            .into_iter()
+            .filter(|item| item["name"] == name)
+            .collect()
+    }
+
+    async fn wait_until(mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition was not met in time");
+            time::delay_for(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn it_follows_container_lifecycle() {
+        let docker = FakeDocker::start();
+        let web = FakeContainer::new("web", "nginx:1.17");
+        docker.run(&web);
+
+        let filter = FilterConfig::new(
+            RulesConfig::default(),
+            RulesConfig::new(vec![], vec!["docmon.ignore".to_string()], vec![], vec![]),
+        );
+        let running = start(&docker, filter);
+        let sink = &running.sink;
+        wait_until(|| !records(sink, "web").is_empty()).await;
+
+        let record = &records(sink, "web")[0];
+        assert_eq!(record["id"], web.id()[..12]);
+        assert_eq!(record["image"], "nginx:1.17");
+        assert_eq!(record["host_name"], "docker-host");
+        assert_eq!(record["cpu_percentage"], 20.0);
+
+        let api = FakeContainer::new("api", "api:2.0");
+        let ignored = FakeContainer::new("ignored", "busybox").with_label("docmon.ignore", "");
+        docker.run(&ignored);
+        docker.run(&api);
+        wait_until(|| !records(sink, "api").is_empty()).await;
+        assert_eq!(docker.stats_requests(ignored.id()), 0);
+
+        docker.stop(web.id());
+        wait_until(|| docker.stats_streams() == 1).await;
+        time::delay_for(Duration::from_millis(100)).await;
+        let stopped = records(sink, "web").len();
+        time::delay_for(Duration::from_millis(100)).await;
+        assert_eq!(records(sink, "web").len(), stopped);
+
+        running.shutdown.send(()).unwrap();
+        running.task.await.unwrap();
+        wait_until(|| docker.stats_streams() == 0).await;
+    }
+
+    #[tokio::test]
+    async fn it_reconnects_dropped_streams() {
+        let docker = FakeDocker::start();
+        let web = FakeContainer::new("web", "nginx:1.17");
+        docker.run(&web);
+
+        let running = start(&docker, FilterConfig::default());
+        let sink = &running.sink;
+        wait_until(|| !records(sink, "web").is_empty()).await;
+
+        // the start event is lost while the events stream is down
+        docker.disconnect();
+        let api = FakeContainer::new("api", "api:2.0");
+        docker.run(&api);
+
+        wait_until(|| docker.stats_requests(web.id()) == 2).await;
+        wait_until(|| !records(sink, "api").is_empty()).await;
+        let reconnected = records(sink, "web").len();
+        wait_until(|| records(sink, "web").len() > reconnected).await;
+
+        running.shutdown.send(()).unwrap();
+        running.task.await.unwrap();
+        wait_until(|| docker.stats_streams() == 0).await;
+    }
+
+    #[test]
+    fn it_maps_container_actions() {
+        assert_eq!(
+            ContainerEvent::from_action("start"),
+            Some(ContainerEvent::Started)
+        );
+        assert_eq!(
+            ContainerEvent::from_action("unpause"),
+            Some(ContainerEvent::Started)
+        );
+        assert_eq!(
+            ContainerEvent::from_action("die"),
+            Some(ContainerEvent::Stopped)
+        );
+        assert_eq!(
+            ContainerEvent::from_action("destroy"),
+            Some(ContainerEvent::Stopped)
+        );
+        assert_eq!(
+            ContainerEvent::from_action("pause"),
+            Some(ContainerEvent::Stopped)
+        );
+        assert_eq!(
+            ContainerEvent::from_action("rename"),
+            Some(ContainerEvent::Changed)
+        );
+        assert_eq!(ContainerEvent::from_action("exec_start: sh"), None);
+    }
 }
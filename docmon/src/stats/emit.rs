@@ -1,27 +1,49 @@
-use std::{convert::TryInto, future::Future};
+use std::{convert::TryFrom, future::Future, sync::Arc, time::Duration};
 
-use bollard::Docker;
-use futures_util::{future, pin_mut, StreamExt};
+use bollard::{container::InspectContainerOptions, errors::ErrorKind, Docker};
+use futures_util::{
+    future::{self, Either},
+    pin_mut, StreamExt,
+};
 use log::{debug, info, warn};
+use tokio::{sync::mpsc::UnboundedSender, time};
 
-use crate::{PublisherHandle, Stats};
+use super::{
+    filter::ContainerInfo,
+    metadata::{Metadata, MetadataResolver},
+    route::Router,
+    sample::{Aggregator, Sampler, SamplingConfig},
+};
+use crate::{backoff::Backoff, Stats};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 pub struct Emitter {
     container_id: String,
     docker: Docker,
-    publisher_handle: PublisherHandle<Stats>,
+    router: Arc<Router>,
+    metadata: Arc<MetadataResolver>,
+    sampling: SamplingConfig,
+    terminated: UnboundedSender<String>,
 }
 
 impl Emitter {
     pub fn new(
         container_id: String,
         docker: Docker,
-        publisher_handle: PublisherHandle<Stats>,
+        router: Arc<Router>,
+        metadata: Arc<MetadataResolver>,
+        sampling: SamplingConfig,
+        terminated: UnboundedSender<String>,
     ) -> Self {
         Self {
             container_id,
             docker,
-            publisher_handle,
+            router,
+            metadata,
+            sampling,
+            terminated,
         }
     }
 
@@ -31,34 +53,135 @@ impl Emitter {
     {
         info!("starting stats emitter for {}", self.container_id);
         let container_id = self.container_id.clone();
+        let terminated = self.terminated.clone();
+
+        let emitter = self.emit();
+        pin_mut!(emitter);
+
+        if let Either::Left((_, shutdown_signal)) = future::select(emitter, shutdown_signal).await {
+            info!("stats emitter for {} terminated", container_id);
+            // lets collector tell this emitter apart from a newer one for the same container
+            drop(shutdown_signal);
+            if let Err(e) = terminated.send(container_id.clone()) {
+                warn!(
+                    "unable to report terminated stats emitter for {}. {:?}",
+                    container_id, e
+                );
+            }
+        }
+
+        info!("stopped stats emitter for {}", container_id);
+    }
+
+    async fn emit(self) {
+        let (container, metadata) = match self
+            .metadata
+            .resolve(&self.docker, &self.container_id)
+            .await
+        {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                warn!(
+                    "unable to resolve metadata for {}. {:?}",
+                    self.container_id, e
+                );
+                let container = ContainerInfo {
+                    id: self.container_id.clone(),
+                    ..ContainerInfo::default()
+                };
+                (container, Metadata::default())
+            }
+        };
+
+        let targets = self.router.select(&container);
+        if targets.is_empty() {
+            info!("no output accepts stats of {}", self.container_id);
+        }
+
+        let (interval, window) = self.sampling.clone().into_parts();
+        let mut sampler = Sampler::new(interval);
+        let mut aggregator = window.map(Aggregator::new);
+
+        let mut backoff = Backoff::new(INITIAL_BACKOFF, MAX_BACKOFF);
 
-        let emitter = async move {
-            loop {
-                let options = bollard::container::StatsOptions { stream: true };
-                let mut stats = self.docker.stats(&self.container_id, Some(options));
-
-                while let Some(stats) = stats.next().await {
-                    match stats {
-                        Ok(stats) => {
-                            debug!("received docker stats: {:?}", stats);
-                            if let Ok(stats) = stats.try_into() {
-                                debug!("converted from docker stats into: {:?}", stats);
-                                self.publisher_handle.send(stats);
+        loop {
+            let options = bollard::container::StatsOptions { stream: true };
+            let mut stats = self.docker.stats(&self.container_id, Some(options));
+            let mut connected = false;
+
+            while let Some(stats) = stats.next().await {
+                match stats {
+                    Ok(stats) => {
+                        if !connected {
+                            info!("stats emitter for {} connected", self.container_id);
+                            connected = true;
+                            backoff.reset();
+                        }
+
+                        debug!("received docker stats: {:?}", stats);
+                        if let Ok(stats) = Stats::try_from(stats) {
+                            debug!("converted from docker stats into: {:?}", stats);
+                            if !sampler.accept(&stats) {
+                                continue;
+                            }
+
+                            let stats = match aggregator.as_mut() {
+                                Some(aggregator) => aggregator.add(stats),
+                                None => Some(stats),
+                            };
+
+                            if let Some(stats) = stats {
+                                targets.send(stats.with_metadata(metadata.clone())).await;
                             }
                         }
-                        Err(e) => warn!(
-                            "unable to read docker stats for {}. {:?}",
-                            self.container_id, e
-                        ),
                     }
+                    Err(e) => warn!(
+                        "unable to read docker stats for {}. {:?}",
+                        self.container_id, e
+                    ),
                 }
             }
-        };
 
-        pin_mut!(emitter);
+            // do not hold samples of the current window while the stream is down
+            if let Some(stats) = aggregator.as_mut().and_then(Aggregator::flush) {
+                targets.send(stats.with_metadata(metadata.clone())).await;
+            }
 
-        future::select(emitter, shutdown_signal).await;
+            if !self.is_running().await {
+                info!(
+                    "container {} is gone. terminating stats emitter",
+                    self.container_id
+                );
+                return;
+            }
 
-        info!("stopped stats emitter for {}", container_id);
+            let delay = backoff.next_delay();
+            info!(
+                "stats emitter for {} backing off for {:?} (attempt {})",
+                self.container_id,
+                delay,
+                backoff.attempt()
+            );
+            time::delay_for(delay).await;
+        }
+    }
+
+    // considers container as running unless docker says otherwise, so that
+    // the emitter keeps retrying while the daemon is unavailable
+    async fn is_running(&self) -> bool {
+        match self
+            .docker
+            .inspect_container(&self.container_id, None::<InspectContainerOptions>)
+            .await
+        {
+            Ok(container) => container.state.running,
+            Err(e) => match e.kind() {
+                ErrorKind::DockerResponseNotFoundError { .. } => false,
+                _ => {
+                    warn!("unable to inspect container {}. {:?}", self.container_id, e);
+                    true
+                }
+            },
+        }
     }
 }
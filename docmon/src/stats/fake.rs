@@ -0,0 +1,412 @@
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bollard::{Docker, API_DEFAULT_VERSION};
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::{
+    body::Bytes,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use hyper_unix_connector::UnixConnector;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+use tokio::{
+    net::UnixListener,
+    sync::broadcast::{self, RecvError},
+    time,
+};
+
+/// Stats record captured from a real Docker engine.
+pub const RECORDED_STATS: &str = include_str!("testdata/stats.json");
+
+const STATS_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A container known to the fake engine.
+#[derive(Debug, Clone)]
+pub struct FakeContainer {
+    id: String,
+    name: String,
+    image: String,
+    labels: HashMap<String, String>,
+    running: bool,
+}
+
+impl FakeContainer {
+    pub fn new(name: &str, image: &str) -> Self {
+        let id = (0..4)
+            .map(|_| format!("{:016x}", rand::random::<u64>()))
+            .collect();
+
+        Self {
+            id,
+            name: name.to_string(),
+            image: image.to_string(),
+            labels: HashMap::new(),
+            running: false,
+        }
+    }
+
+    pub fn with_label(mut self, key: &str, value: &str) -> Self {
+        self.labels.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn summary(&self) -> Value {
+        json!({
+            "Id": self.id,
+            "Names": [format!("/{}", self.name)],
+            "Image": self.image,
+            "ImageID": "sha256:0123456789abcdef",
+            "Command": "/bin/sh",
+            "Created": 1588327200,
+            "State": "running",
+            "Status": "Up 1 second",
+            "Ports": [],
+            "Labels": self.labels,
+            "Mounts": [],
+            "NetworkSettings": { "Networks": {} },
+            "HostConfig": { "NetworkMode": "default" },
+        })
+    }
+
+    fn inspect(&self) -> Value {
+        json!({
+            "Id": self.id,
+            "Created": "2020-05-01T10:00:00Z",
+            "Path": "/bin/sh",
+            "Args": [],
+            "Config": { "Image": self.image, "Labels": self.labels },
+            "State": {
+                "Status": if self.running { "running" } else { "exited" },
+                "Running": self.running,
+                "Paused": false,
+                "Restarting": false,
+                "OOMKilled": false,
+                "Dead": false,
+                "Pid": 0,
+                "ExitCode": 0,
+                "Error": "",
+                "StartedAt": "2020-05-01T10:00:00Z",
+                "FinishedAt": "0001-01-01T00:00:00Z",
+            },
+            "Image": "sha256:0123456789abcdef",
+            "NetworkSettings": {
+                "Networks": {},
+                "IPAddress": "",
+                "IPPrefixLen": 0,
+                "MacAddress": "",
+                "Gateway": "",
+                "Bridge": "",
+                "EndpointID": "",
+                "SandboxKey": "",
+                "GlobalIPv6Address": "",
+                "GlobalIPv6PrefixLen": 0,
+                "IPv6Gateway": "",
+                "LinkLocalIPv6Address": "",
+                "LinkLocalIPv6PrefixLen": 0,
+                "SandboxID": "",
+                "HairpinMode": false,
+                "Ports": {},
+            },
+            "ResolvConfPath": "",
+            "HostnamePath": "",
+            "HostsPath": "",
+            "LogPath": "",
+            "Name": format!("/{}", self.name),
+            "Driver": "overlay2",
+            "Mounts": [],
+            "HostConfig": {},
+            "RestartCount": 0,
+            "Platform": "linux",
+            "MountLabel": "",
+            "ProcessLabel": "",
+            "AppArmorProfile": "",
+            "GraphDriver": { "Name": "overlay2" },
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Signal {
+    Event(Value),
+    Disconnect,
+}
+
+#[derive(Default)]
+struct State {
+    containers: Vec<FakeContainer>,
+    // bumped to close every open stream as if the engine restarted
+    generation: u64,
+    stats_streams: usize,
+    stats_requests: HashMap<String, usize>,
+}
+
+impl State {
+    fn container(&self, id: &str) -> Option<&FakeContainer> {
+        self.containers
+            .iter()
+            .find(|container| container.id == id || container.name == id)
+    }
+}
+
+/// In-process Docker engine API served over a Unix socket in a temporary
+/// directory. Containers are started and stopped by the test, while stats
+/// streams replay the recorded stats with the current time.
+pub struct FakeDocker {
+    socket: String,
+    state: Arc<Mutex<State>>,
+    signals: broadcast::Sender<Signal>,
+    _dir: TempDir,
+}
+
+impl FakeDocker {
+    pub fn start() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("docker.sock").to_str().unwrap().to_string();
+
+        let state = Arc::new(Mutex::new(State::default()));
+        let (signals, _) = broadcast::channel(64);
+
+        let shared = (state.clone(), signals.clone());
+        let make_svc = make_service_fn(move |_| {
+            let (state, signals) = shared.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    handle(state.clone(), signals.clone(), req)
+                }))
+            }
+        });
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = Server::builder(UnixConnector::from(listener)).serve(make_svc);
+        tokio::spawn(server);
+
+        Self {
+            socket,
+            state,
+            signals,
+            _dir: dir,
+        }
+    }
+
+    /// Returns the socket address in the form accepted by `CollectorConfig`.
+    pub fn socket(&self) -> String {
+        format!("unix://{}", self.socket)
+    }
+
+    pub fn docker(&self) -> Docker {
+        Docker::connect_with_unix(&self.socket, 120, API_DEFAULT_VERSION).unwrap()
+    }
+
+    /// Starts the container and emits a `start` event.
+    pub fn run(&self, container: &FakeContainer) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.containers.retain(|known| known.id != container.id);
+            state.containers.push(FakeContainer {
+                running: true,
+                ..container.clone()
+            });
+        }
+
+        self.emit("start", &container.id);
+    }
+
+    /// Stops the container and emits a `die` event. Its stats stream ends.
+    pub fn stop(&self, id: &str) {
+        for known in &mut self.state.lock().unwrap().containers {
+            if known.id == id {
+                known.running = false;
+            }
+        }
+
+        self.emit("die", id);
+    }
+
+    /// Closes all open stats and events streams while containers keep running.
+    pub fn disconnect(&self) {
+        self.state.lock().unwrap().generation += 1;
+        let _ = self.signals.send(Signal::Disconnect);
+    }
+
+    /// Returns a number of stats streams currently open.
+    pub fn stats_streams(&self) -> usize {
+        self.state.lock().unwrap().stats_streams
+    }
+
+    /// Returns how many times stats of the container were requested.
+    pub fn stats_requests(&self, id: &str) -> usize {
+        let state = self.state.lock().unwrap();
+        state.stats_requests.get(id).copied().unwrap_or_default()
+    }
+
+    fn emit(&self, action: &str, id: &str) {
+        let now = Utc::now();
+        let event = json!({
+            "Type": "container",
+            "Action": action,
+            "Actor": { "ID": id, "Attributes": {} },
+            "time": now.timestamp(),
+            "timeNano": now.timestamp_nanos(),
+            "scope": "local",
+        });
+
+        // nobody may be subscribed yet, the collector reconciles on subscribe
+        let _ = self.signals.send(Signal::Event(event));
+    }
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    signals: broadcast::Sender<Signal>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    // bollard may prefix paths with the API version
+    let path = req.uri().path();
+    let path = match path.strip_prefix("/v1.") {
+        Some(rest) => rest.find('/').map_or("", |index| &rest[index..]),
+        None => path,
+    };
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let segments = segments.iter().map(String::as_str).collect::<Vec<_>>();
+
+    if req.method() != Method::GET {
+        return Ok(status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let res = match segments.as_slice() {
+        ["info"] => json_response(json!({ "ID": "ENGINE:ID", "Name": "docker-host" })),
+        ["containers", "json"] => {
+            let state = state.lock().unwrap();
+            let containers = state
+                .containers
+                .iter()
+                .filter(|container| container.running)
+                .map(FakeContainer::summary)
+                .collect::<Vec<_>>();
+            json_response(Value::from(containers))
+        }
+        ["containers", id, "json"] => match state.lock().unwrap().container(id) {
+            Some(container) => json_response(container.inspect()),
+            None => not_found(id),
+        },
+        ["containers", id, "stats"] => stats(state, id, req.uri().query()),
+        ["events"] => events(signals.subscribe()),
+        _ => not_found(path),
+    };
+
+    Ok(res)
+}
+
+fn stats(state: Arc<Mutex<State>>, id: &str, query: Option<&str>) -> Response<Body> {
+    let (generation, container) = {
+        let mut state = state.lock().unwrap();
+        let container = match state.container(id) {
+            Some(container) if container.running => container.clone(),
+            Some(_) => return status(StatusCode::CONFLICT),
+            None => return not_found(id),
+        };
+        *state
+            .stats_requests
+            .entry(container.id.clone())
+            .or_default() += 1;
+        state.stats_streams += 1;
+        (state.generation, container)
+    };
+
+    let stream = query.is_none_or(|query| !query.contains("stream=false"));
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut preread = Utc::now();
+        loop {
+            time::delay_for(STATS_INTERVAL).await;
+
+            let open = {
+                let state = state.lock().unwrap();
+                state.generation == generation
+                    && state
+                        .container(&container.id)
+                        .is_some_and(|container| container.running)
+            };
+            if !open {
+                break;
+            }
+
+            let read = Utc::now();
+            let record = replay(&container, read, preread);
+            preread = read;
+
+            if sender.send_data(Bytes::from(record)).await.is_err() || !stream {
+                break;
+            }
+        }
+
+        state.lock().unwrap().stats_streams -= 1;
+    });
+
+    Response::new(body)
+}
+
+fn replay(container: &FakeContainer, read: DateTime<Utc>, preread: DateTime<Utc>) -> String {
+    let mut record: Value = serde_json::from_str(RECORDED_STATS).unwrap();
+    record["id"] = json!(container.id);
+    record["name"] = json!(format!("/{}", container.name));
+    record["read"] = json!(read.to_rfc3339_opts(SecondsFormat::Nanos, true));
+    record["preread"] = json!(preread.to_rfc3339_opts(SecondsFormat::Nanos, true));
+
+    format!("{}\n", record)
+}
+
+fn events(mut signals: broadcast::Receiver<Signal>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        loop {
+            match signals.recv().await {
+                Ok(Signal::Event(event)) => {
+                    let line = format!("{}\n", event);
+                    if sender.send_data(Bytes::from(line)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Signal::Disconnect) | Err(RecvError::Closed) => break,
+                Err(RecvError::Lagged(_)) => {}
+            }
+        }
+    });
+
+    Response::new(body)
+}
+
+fn json_response(value: Value) -> Response<Body> {
+    let mut res = Response::new(Body::from(value.to_string()));
+    res.headers_mut()
+        .insert("Content-Type", "application/json".parse().unwrap());
+    res
+}
+
+fn not_found(what: &str) -> Response<Body> {
+    let mut res = json_response(json!({ "message": format!("No such object: {}", what) }));
+    *res.status_mut() = StatusCode::NOT_FOUND;
+    res
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = status;
+    res
+}
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use bollard::container::{APIContainers, Container};
+use regex::Regex;
+use serde::Deserialize;
+
+use super::metadata::COMPOSE_PROJECT_LABEL;
+
+/// A subset of container properties the filter rules are checked against.
+#[derive(Debug, Clone, Default)]
+pub struct ContainerInfo {
+    pub id: String,
+    pub name: String,
+    pub image: String,
+    pub labels: HashMap<String, String>,
+}
+
+impl From<&APIContainers> for ContainerInfo {
+    fn from(container: &APIContainers) -> Self {
+        Self {
+            id: container.id.clone(),
+            name: container
+                .names
+                .first()
+                .map(|name| name.trim_start_matches('/').to_string())
+                .unwrap_or_default(),
+            image: container.image.clone(),
+            labels: container.labels.clone(),
+        }
+    }
+}
+
+impl From<&Container> for ContainerInfo {
+    fn from(container: &Container) -> Self {
+        Self {
+            id: container.id.clone(),
+            name: container.name.trim_start_matches('/').to_string(),
+            image: container
+                .config
+                .image
+                .clone()
+                .unwrap_or_else(|| container.image.clone()),
+            labels: container.config.labels.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Decides which containers get a stats emitter.
+///
+/// A container is accepted when it matches any of the include rules (or
+/// there are no include rules at all) and does not match any exclude rule.
+#[derive(Debug, Default)]
+pub struct Filter {
+    include: Rules,
+    exclude: Rules,
+}
+
+impl Filter {
+    pub fn new(config: FilterConfig) -> Result<Self> {
+        let (include, exclude) = config.into_parts();
+
+        Ok(Self {
+            include: Rules::new(include).context("invalid include filter")?,
+            exclude: Rules::new(exclude).context("invalid exclude filter")?,
+        })
+    }
+
+    pub fn matches(&self, container: &ContainerInfo) -> bool {
+        (self.include.is_empty() || self.include.matches(container))
+            && !self.exclude.matches(container)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    names: Vec<Pattern>,
+    labels: Vec<LabelRule>,
+    images: Vec<Pattern>,
+    compose_projects: Vec<Pattern>,
+}
+
+impl Rules {
+    fn new(config: RulesConfig) -> Result<Self> {
+        Ok(Self {
+            names: parse_all(&config.names, Pattern::new)?,
+            labels: parse_all(&config.labels, LabelRule::new)?,
+            images: parse_all(&config.images, Pattern::new)?,
+            compose_projects: parse_all(&config.compose_projects, Pattern::new)?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty()
+            && self.labels.is_empty()
+            && self.images.is_empty()
+            && self.compose_projects.is_empty()
+    }
+
+    fn matches(&self, container: &ContainerInfo) -> bool {
+        let compose_project = container.labels.get(COMPOSE_PROJECT_LABEL);
+
+        self.names.iter().any(|name| name.matches(&container.name))
+            || self
+                .labels
+                .iter()
+                .any(|label| label.matches(&container.labels))
+            || self
+                .images
+                .iter()
+                .any(|image| image.matches(&container.image))
+            || compose_project.is_some_and(|project| {
+                self.compose_projects
+                    .iter()
+                    .any(|pattern| pattern.matches(project))
+            })
+    }
+}
+
+fn parse_all<T>(values: &[String], parse: impl Fn(&str) -> Result<T>) -> Result<Vec<T>> {
+    values.iter().map(|value| parse(value)).collect()
+}
+
+/// A glob pattern, or a regular expression when enclosed in slashes (`/^web-\d+$/`).
+#[derive(Debug)]
+enum Pattern {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn new(value: &str) -> Result<Self> {
+        if value.len() > 1 && value.starts_with('/') && value.ends_with('/') {
+            let regex = Regex::new(&value[1..value.len() - 1])
+                .with_context(|| format!("invalid regex {}", value))?;
+            Ok(Pattern::Regex(regex))
+        } else {
+            let glob = glob::Pattern::new(value)
+                .with_context(|| format!("invalid glob pattern {}", value))?;
+            Ok(Pattern::Glob(glob))
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Pattern::Glob(glob) => glob.matches(value),
+            Pattern::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+/// Matches a label by key (`tier`) or by key and value pattern (`tier=front*`).
+#[derive(Debug)]
+struct LabelRule {
+    key: String,
+    value: Option<Pattern>,
+}
+
+impl LabelRule {
+    fn new(value: &str) -> Result<Self> {
+        let mut parts = value.splitn(2, '=');
+        let key = parts.next().unwrap_or_default().to_string();
+        let value = parts.next().map(Pattern::new).transpose()?;
+
+        Ok(Self { key, value })
+    }
+
+    fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        labels.get(&self.key).is_some_and(|label| {
+            self.value
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches(label))
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FilterConfig {
+    #[serde(default)]
+    include: RulesConfig,
+    #[serde(default)]
+    exclude: RulesConfig,
+}
+
+impl FilterConfig {
+    pub fn new(include: RulesConfig, exclude: RulesConfig) -> Self {
+        Self { include, exclude }
+    }
+
+    pub fn into_parts(self) -> (RulesConfig, RulesConfig) {
+        (self.include, self.exclude)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RulesConfig {
+    names: Vec<String>,
+    labels: Vec<String>,
+    images: Vec<String>,
+    compose_projects: Vec<String>,
+}
+
+impl RulesConfig {
+    pub fn new(
+        names: Vec<String>,
+        labels: Vec<String>,
+        images: Vec<String>,
+        compose_projects: Vec<String>,
+    ) -> Self {
+        Self {
+            names,
+            labels,
+            images,
+            compose_projects,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(ToString::to_string).collect()
+    }
+
+    fn container(name: &str, image: &str, labels: &[(&str, &str)]) -> ContainerInfo {
+        ContainerInfo {
+            id: "0123456789ab".to_string(),
+            name: name.to_string(),
+            image: image.to_string(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn it_accepts_everything_by_default() {
+        let filter = Filter::new(FilterConfig::default()).unwrap();
+
+        assert!(filter.matches(&container("web", "nginx:latest", &[])));
+    }
+
+    #[test]
+    fn it_matches_names_by_glob_and_regex() {
+        let include = RulesConfig {
+            names: strings(&["web-*", r"/^api-\d+$/"]),
+            ..RulesConfig::default()
+        };
+        let filter = Filter::new(FilterConfig::new(include, RulesConfig::default())).unwrap();
+
+        assert!(filter.matches(&container("web-1", "nginx", &[])));
+        assert!(filter.matches(&container("api-42", "app", &[])));
+        assert!(!filter.matches(&container("api-x", "app", &[])));
+        assert!(!filter.matches(&container("db", "postgres", &[])));
+    }
+
+    #[test]
+    fn it_matches_labels_by_key_and_value() {
+        let include = RulesConfig {
+            labels: strings(&["monitor", "tier=front*"]),
+            ..RulesConfig::default()
+        };
+        let filter = Filter::new(FilterConfig::new(include, RulesConfig::default())).unwrap();
+
+        assert!(filter.matches(&container("a", "app", &[("monitor", "")])));
+        assert!(filter.matches(&container("b", "app", &[("tier", "frontend")])));
+        assert!(!filter.matches(&container("c", "app", &[("tier", "backend")])));
+        assert!(!filter.matches(&container("d", "app", &[])));
+    }
+
+    #[test]
+    fn it_excludes_images_and_compose_projects() {
+        let exclude = RulesConfig {
+            images: strings(&["moby/buildkit*"]),
+            compose_projects: strings(&["ci"]),
+            ..RulesConfig::default()
+        };
+        let filter = Filter::new(FilterConfig::new(RulesConfig::default(), exclude)).unwrap();
+
+        assert!(!filter.matches(&container("builder", "moby/buildkit:v0.7", &[])));
+        assert!(!filter.matches(&container(
+            "ci_db_1",
+            "postgres",
+            &[(COMPOSE_PROJECT_LABEL, "ci")]
+        )));
+        assert!(filter.matches(&container(
+            "shop_db_1",
+            "postgres",
+            &[(COMPOSE_PROJECT_LABEL, "shop")]
+        )));
+    }
+
+    #[test]
+    fn it_applies_exclude_after_include() {
+        let include = RulesConfig {
+            names: strings(&["web-*"]),
+            ..RulesConfig::default()
+        };
+        let exclude = RulesConfig {
+            names: strings(&["*-sidecar"]),
+            ..RulesConfig::default()
+        };
+        let filter = Filter::new(FilterConfig::new(include, exclude)).unwrap();
+
+        assert!(filter.matches(&container("web-1", "nginx", &[])));
+        assert!(!filter.matches(&container("web-1-sidecar", "envoy", &[])));
+    }
+
+    #[test]
+    fn it_rejects_invalid_patterns() {
+        let include = RulesConfig {
+            names: strings(&["/(/"]),
+            ..RulesConfig::default()
+        };
+
+        assert!(Filter::new(FilterConfig::new(include, RulesConfig::default())).is_err());
+    }
+
+    #[test]
+    fn it_converts_toml_config() {
+        let content = r#"
+            [include]
+            names = ["web-*"]
+            labels = ["tier=frontend"]
+
+            [exclude]
+            compose_projects = ["ci"]
+        "#;
+
+        let mut config = config::Config::new();
+        config
+            .merge(config::File::from_str(content, config::FileFormat::Toml))
+            .unwrap();
+        let config: FilterConfig = config.try_into().unwrap();
+
+        assert_eq!(config.include.names, vec!["web-*"]);
+        assert_eq!(config.include.labels, vec!["tier=frontend"]);
+        assert_eq!(config.exclude.compose_projects, vec!["ci"]);
+        assert!(config.exclude.names.is_empty());
+    }
+}
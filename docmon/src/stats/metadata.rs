@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use bollard::{container::InspectContainerOptions, Docker};
+use hyper::{body, Body, StatusCode};
+use hyper_unix_connector::{UnixClient, Uri};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use super::filter::ContainerInfo;
+
+pub(super) const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+const KUBERNETES_POD_NAME_LABEL: &str = "io.kubernetes.pod.name";
+const KUBERNETES_POD_NAMESPACE_LABEL: &str = "io.kubernetes.pod.namespace";
+
+/// Container and host properties attached to every stats record.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    engine_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compose_project: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compose_service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pod_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pod_namespace: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+}
+
+/// Docker engine properties which are not exposed by bollard.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EngineInfo {
+    #[serde(rename = "ID")]
+    id: String,
+    name: String,
+}
+
+impl EngineInfo {
+    pub async fn load(socket: &str) -> Result<Self> {
+        let socket = socket.trim_start_matches("unix://");
+        let client = hyper::Client::builder().build::<_, Body>(UnixClient);
+
+        let res = client.get(Uri::new(socket, "/info").into()).await?;
+        if res.status() != StatusCode::OK {
+            return Err(anyhow!(
+                "unable to get docker engine info: {}",
+                res.status()
+            ));
+        }
+
+        let bytes = body::to_bytes(res.into_body()).await?;
+        let info = serde_json::from_slice(&bytes)?;
+
+        Ok(info)
+    }
+}
+
+/// Resolves metadata for a container once, so it can be reused for every sample.
+#[derive(Debug, Clone)]
+pub struct MetadataResolver {
+    config: MetadataConfig,
+    engine: Option<EngineInfo>,
+}
+
+impl MetadataResolver {
+    pub fn new(config: MetadataConfig) -> Self {
+        Self {
+            config,
+            engine: None,
+        }
+    }
+
+    pub fn requires_engine(&self) -> bool {
+        self.config.host
+    }
+
+    pub fn set_engine(&mut self, engine: EngineInfo) {
+        self.engine = Some(engine);
+    }
+
+    pub async fn resolve(
+        &self,
+        docker: &Docker,
+        container_id: &str,
+    ) -> Result<(ContainerInfo, Metadata)> {
+        let container = docker
+            .inspect_container(container_id, None::<InspectContainerOptions>)
+            .await?;
+        debug!("inspected container {}", container_id);
+
+        let image_digest = if self.config.image {
+            match docker.inspect_image(&container.image).await {
+                Ok(image) => image
+                    .repo_digests
+                    .first()
+                    .and_then(|digest| digest.split_once('@'))
+                    .map(|(_, digest)| digest.to_string())
+                    .or(Some(image.id)),
+                Err(e) => {
+                    warn!("unable to inspect image {}. {:?}", container.image, e);
+                    Some(container.image.clone())
+                }
+            }
+        } else {
+            None
+        };
+
+        let info = ContainerInfo::from(&container);
+        let labels = container.config.labels.unwrap_or_default();
+        let image = container.config.image;
+
+        Ok((info, self.build(labels, image, image_digest)))
+    }
+
+    fn build(
+        &self,
+        labels: HashMap<String, String>,
+        image: Option<String>,
+        image_digest: Option<String>,
+    ) -> Metadata {
+        let label = |key: &str| labels.get(key).cloned();
+
+        let (host_name, engine_id) = match (&self.engine, self.config.host) {
+            (Some(engine), true) => (Some(engine.name.clone()), Some(engine.id.clone())),
+            _ => (None, None),
+        };
+
+        let (image, image_digest) = if self.config.image {
+            (image, image_digest)
+        } else {
+            (None, None)
+        };
+
+        let (compose_project, compose_service) = if self.config.compose {
+            (label(COMPOSE_PROJECT_LABEL), label(COMPOSE_SERVICE_LABEL))
+        } else {
+            (None, None)
+        };
+
+        let (pod_name, pod_namespace) = if self.config.kubernetes {
+            (
+                label(KUBERNETES_POD_NAME_LABEL),
+                label(KUBERNETES_POD_NAMESPACE_LABEL),
+            )
+        } else {
+            (None, None)
+        };
+
+        let labels = self
+            .config
+            .labels
+            .iter()
+            .filter_map(|key| label(key).map(|value| (key.clone(), value)))
+            .collect();
+
+        Metadata {
+            host_name,
+            engine_id,
+            image,
+            image_digest,
+            compose_project,
+            compose_service,
+            pod_name,
+            pod_namespace,
+            labels,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MetadataConfig {
+    host: bool,
+    image: bool,
+    compose: bool,
+    kubernetes: bool,
+    labels: Vec<String>,
+}
+
+impl Default for MetadataConfig {
+    fn default() -> Self {
+        Self {
+            host: true,
+            image: true,
+            compose: true,
+            kubernetes: true,
+            labels: Vec::new(),
+        }
+    }
+}
+
+impl MetadataConfig {
+    pub fn new(
+        host: bool,
+        image: bool,
+        compose: bool,
+        kubernetes: bool,
+        labels: Vec<String>,
+    ) -> Self {
+        Self {
+            host,
+            image,
+            compose,
+            kubernetes,
+            labels,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels() -> HashMap<String, String> {
+        vec![
+            (COMPOSE_PROJECT_LABEL, "shop"),
+            (COMPOSE_SERVICE_LABEL, "web"),
+            (KUBERNETES_POD_NAME_LABEL, "web-5d8f7"),
+            (KUBERNETES_POD_NAMESPACE_LABEL, "default"),
+            ("team", "payments"),
+            ("secret", "value"),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+    }
+
+    fn engine() -> EngineInfo {
+        EngineInfo {
+            id: "ABCD:EFGH".to_string(),
+            name: "docker-host".to_string(),
+        }
+    }
+
+    #[test]
+    fn it_builds_metadata_from_labels() {
+        let config = MetadataConfig {
+            labels: vec!["team".to_string(), "missing".to_string()],
+            ..MetadataConfig::default()
+        };
+        let mut resolver = MetadataResolver::new(config);
+        resolver.set_engine(engine());
+
+        let metadata = resolver.build(
+            labels(),
+            Some("nginx:1.19".to_string()),
+            Some("sha256:0123".to_string()),
+        );
+
+        assert_eq!(metadata.host_name.as_deref(), Some("docker-host"));
+        assert_eq!(metadata.engine_id.as_deref(), Some("ABCD:EFGH"));
+        assert_eq!(metadata.image.as_deref(), Some("nginx:1.19"));
+        assert_eq!(metadata.image_digest.as_deref(), Some("sha256:0123"));
+        assert_eq!(metadata.compose_project.as_deref(), Some("shop"));
+        assert_eq!(metadata.compose_service.as_deref(), Some("web"));
+        assert_eq!(metadata.pod_name.as_deref(), Some("web-5d8f7"));
+        assert_eq!(metadata.pod_namespace.as_deref(), Some("default"));
+        assert_eq!(
+            metadata.labels.into_iter().collect::<Vec<_>>(),
+            vec![("team".to_string(), "payments".to_string())]
+        );
+    }
+
+    #[test]
+    fn it_skips_disabled_metadata() {
+        let config = MetadataConfig::new(false, false, false, false, Vec::new());
+        let mut resolver = MetadataResolver::new(config);
+        resolver.set_engine(engine());
+
+        let metadata = resolver.build(labels(), Some("nginx:1.19".to_string()), None);
+
+        assert_eq!(metadata, Metadata::default());
+        assert_eq!(serde_json::to_string(&metadata).unwrap(), "{}");
+    }
+}
@@ -1,16 +1,28 @@
 use std::convert::TryFrom;
 
 use anyhow::anyhow;
-use serde::Serialize;
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
 
 mod collect;
 mod emit;
-
-pub use collect::Collector;
-
-#[derive(Debug, Clone, Serialize)]
+#[cfg(test)]
+mod fake;
+mod filter;
+mod metadata;
+mod route;
+mod sample;
+
+pub use collect::{Collector, CollectorConfig};
+pub use filter::{FilterConfig, RulesConfig};
+pub use metadata::{Metadata, MetadataConfig};
+pub use route::Router;
+pub use sample::{Aggregate, SamplingConfig};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub struct Stats {
-    timestamp: String,
+    #[serde(serialize_with = "serialize_timestamp")]
+    timestamp: DateTime<Utc>,
     id: String,
     name: String,
     cpu_percentage: Option<f64>,
@@ -22,6 +34,17 @@ pub struct Stats {
     block_read: Option<u64>,
     block_write: Option<u64>,
     pid: Option<u64>,
+    #[serde(flatten)]
+    metadata: Metadata,
+    #[serde(flatten)]
+    aggregate: Option<Aggregate>,
+}
+
+impl Stats {
+    pub(crate) fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
 }
 
 impl TryFrom<bollard::container::Stats> for Stats {
@@ -109,12 +132,8 @@ impl TryFrom<bollard::container::Stats> for Stats {
                     (Some(read), Some(write))
                 });
 
-        let timestamp = stats
-            .read
-            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
-
         Ok(Self {
-            timestamp,
+            timestamp: stats.read,
             id: stats.id[..12].into(),
             name: stats.name[1..].into(),
             cpu_percentage,
@@ -126,6 +145,57 @@ impl TryFrom<bollard::container::Stats> for Stats {
             block_read,
             block_write,
             pid: stats.pids_stats.current,
+            metadata: Metadata::default(),
+            aggregate: None,
         })
     }
 }
+
+fn serialize_timestamp<S>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn recorded() -> bollard::container::Stats {
+        serde_json::from_str(fake::RECORDED_STATS).unwrap()
+    }
+
+    #[test]
+    fn it_converts_recorded_stats() {
+        let stats = Stats::try_from(recorded()).unwrap();
+
+        let expected = Stats {
+            timestamp: Utc.ymd(2020, 5, 1).and_hms(10, 0, 1),
+            id: "3f2a9c1e8b7d".to_string(),
+            name: "web".to_string(),
+            cpu_percentage: Some(20.0),
+            memory: Some(100_663_296),
+            memory_percentage: Some(9.375),
+            memory_limit: Some(1_073_741_824),
+            network_rx: Some(1024),
+            network_tx: Some(2048),
+            block_read: Some(4096),
+            block_write: Some(8192),
+            pid: Some(5),
+            metadata: Metadata::default(),
+            aggregate: None,
+        };
+        assert_eq!(stats, expected);
+    }
+
+    #[test]
+    fn it_rejects_stats_without_current_measurement() {
+        let mut stats = recorded();
+        stats.preread = stats.read + chrono::Duration::seconds(1);
+
+        assert!(Stats::try_from(stats).is_err());
+    }
+}
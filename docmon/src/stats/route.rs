@@ -0,0 +1,210 @@
+use anyhow::Result;
+use log::warn;
+
+use super::filter::{ContainerInfo, Filter, FilterConfig};
+use crate::{
+    channel::{self, ChannelConfig, Overflow, Sender},
+    PublisherHandle, Stats,
+};
+
+/// Delivers stats records to every output whose filter accepts the container.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+struct Route {
+    filter: Filter,
+    target: Target,
+}
+
+#[derive(Clone)]
+enum Target {
+    /// An output which drops records when full, so sending never waits.
+    Direct(PublisherHandle<Stats>),
+    /// The queue of a task feeding an output which blocks when full.
+    Forwarded(Sender<Stats>),
+}
+
+impl Target {
+    fn new(handle: PublisherHandle<Stats>) -> Self {
+        match handle.overflow() {
+            Overflow::Block => Target::Forwarded(forward(handle)),
+            Overflow::DropNewest | Overflow::DropOldest => Target::Direct(handle),
+        }
+    }
+}
+
+// waiting for room in the channel of a blocking output holds back only its
+// own task, records are dropped once the queue in front of it is full too
+fn forward(handle: PublisherHandle<Stats>) -> Sender<Stats> {
+    let config = ChannelConfig::new(handle.capacity(), Overflow::DropNewest);
+    let (sender, mut receiver) = channel::channel(config);
+
+    tokio::spawn(async move {
+        let mut reported_dropped = 0;
+        while let Some(stats) = receiver.recv().await {
+            handle.send(stats).await;
+
+            let dropped = receiver.dropped();
+            if dropped > reported_dropped {
+                warn!(
+                    "blocked output is full, dropped {} record(s) ({} total)",
+                    dropped - reported_dropped,
+                    dropped
+                );
+                reported_dropped = dropped;
+            }
+        }
+    });
+
+    sender
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an output. Outputs which block when full are fed by a task of
+    /// their own, which needs a runtime.
+    pub fn route(mut self, handle: PublisherHandle<Stats>, filter: FilterConfig) -> Result<Self> {
+        self.routes.push(Route {
+            filter: Filter::new(filter)?,
+            target: Target::new(handle),
+        });
+        Ok(self)
+    }
+
+    pub(crate) fn select(&self, container: &ContainerInfo) -> Targets {
+        Targets(
+            self.routes
+                .iter()
+                .filter(|route| route.filter.matches(container))
+                .map(|route| route.target.clone())
+                .collect(),
+        )
+    }
+}
+
+impl From<PublisherHandle<Stats>> for Router {
+    fn from(handle: PublisherHandle<Stats>) -> Self {
+        Self {
+            routes: vec![Route {
+                filter: Filter::default(),
+                target: Target::new(handle),
+            }],
+        }
+    }
+}
+
+/// Outputs selected for a single container.
+pub(crate) struct Targets(Vec<Target>);
+
+impl Targets {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // never waits for a full output, see `forward`
+    pub async fn send(&self, stats: Stats) {
+        for target in &self.0 {
+            match target {
+                Target::Direct(handle) => handle.send(stats.clone()).await,
+                Target::Forwarded(sender) => {
+                    // only fails once the forwarding task is gone
+                    let _ = sender.send(stats.clone()).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+    use tokio::time;
+
+    use super::*;
+    use crate::{MemorySink, Publisher, PublisherConfig, RulesConfig};
+
+    fn stats() -> Stats {
+        Stats {
+            timestamp: Utc.ymd(2020, 5, 1).and_hms(12, 0, 0),
+            id: "0123456789ab".to_string(),
+            name: "web".to_string(),
+            cpu_percentage: Some(20.5),
+            memory: None,
+            memory_percentage: None,
+            memory_limit: None,
+            network_rx: None,
+            network_tx: None,
+            block_read: None,
+            block_write: None,
+            pid: None,
+            metadata: Default::default(),
+            aggregate: None,
+        }
+    }
+
+    fn container(name: &str) -> ContainerInfo {
+        ContainerInfo {
+            name: name.to_string(),
+            ..ContainerInfo::default()
+        }
+    }
+
+    #[test]
+    fn it_selects_outputs_by_filter() {
+        let handle = || {
+            Publisher::<Stats>::new(MemorySink::new(), PublisherConfig::new("Stats", 10, 10))
+                .unwrap()
+                .1
+        };
+        let web = FilterConfig::new(
+            RulesConfig::new(vec!["web-*".to_string()], vec![], vec![], vec![]),
+            RulesConfig::default(),
+        );
+
+        let router = Router::new()
+            .route(handle(), FilterConfig::default())
+            .unwrap()
+            .route(handle(), web)
+            .unwrap();
+
+        assert_eq!(router.select(&container("web-1")).0.len(), 2);
+        assert_eq!(router.select(&container("db-1")).0.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn it_does_not_hold_back_outputs_behind_a_blocked_one() {
+        let config = |overflow| {
+            PublisherConfig::new("Stats", 10, 10).with_channel(ChannelConfig::new(1, overflow))
+        };
+        // neither publisher runs, so the blocking output stays full
+        let (_blocked, blocked) =
+            Publisher::<Stats>::new(MemorySink::new(), config(Overflow::Block)).unwrap();
+        let (_other, other) =
+            Publisher::<Stats>::new(MemorySink::new(), config(Overflow::DropOldest)).unwrap();
+
+        let router = Router::new()
+            .route(blocked.clone(), FilterConfig::default())
+            .unwrap()
+            .route(other.clone(), FilterConfig::default())
+            .unwrap();
+        let targets = router.select(&container("web-1"));
+
+        for _ in 0..5 {
+            time::timeout(Duration::from_secs(1), targets.send(stats()))
+                .await
+                .unwrap();
+        }
+        time::delay_for(Duration::from_millis(50)).await;
+
+        assert_eq!(blocked.queued(), 1);
+        assert_eq!(other.queued(), 1);
+        assert_eq!(other.dropped(), 4);
+    }
+}
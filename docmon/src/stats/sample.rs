@@ -0,0 +1,295 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{serialize_timestamp, Stats};
+
+/// Drops samples which arrive sooner than `interval` after the last accepted one.
+#[derive(Debug)]
+pub struct Sampler {
+    interval: chrono::Duration,
+    last: Option<DateTime<Utc>>,
+}
+
+impl Sampler {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval: chrono::Duration::from_std(interval)
+                .unwrap_or_else(|_| chrono::Duration::zero()),
+            last: None,
+        }
+    }
+
+    pub fn accept(&mut self, stats: &Stats) -> bool {
+        match self.last {
+            Some(last) if stats.timestamp - last < self.interval => false,
+            _ => {
+                self.last = Some(stats.timestamp);
+                true
+            }
+        }
+    }
+}
+
+/// Folds samples of a single container into one record per window.
+///
+/// The record keeps the last sample values and adds min, max and average of
+/// the gauges observed during the window.
+#[derive(Debug)]
+pub struct Aggregator {
+    window: chrono::Duration,
+    start: Option<DateTime<Utc>>,
+    last: Option<Stats>,
+    samples: u32,
+    cpu_percentage: Summary,
+    memory: Summary,
+    memory_percentage: Summary,
+    pid: Summary,
+}
+
+impl Aggregator {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: chrono::Duration::from_std(window).unwrap_or_else(|_| chrono::Duration::zero()),
+            start: None,
+            last: None,
+            samples: 0,
+            cpu_percentage: Summary::default(),
+            memory: Summary::default(),
+            memory_percentage: Summary::default(),
+            pid: Summary::default(),
+        }
+    }
+
+    /// Adds a sample and returns the aggregated record once the window is over.
+    pub fn add(&mut self, stats: Stats) -> Option<Stats> {
+        let completed = match self.start {
+            Some(start) if stats.timestamp - start >= self.window => self.flush(),
+            _ => None,
+        };
+
+        self.start.get_or_insert(stats.timestamp);
+        self.samples += 1;
+        self.cpu_percentage.add(stats.cpu_percentage);
+        self.memory.add(stats.memory.map(|memory| memory as f64));
+        self.memory_percentage.add(stats.memory_percentage);
+        self.pid.add(stats.pid.map(|pid| pid as f64));
+        self.last = Some(stats);
+
+        completed
+    }
+
+    /// Returns the aggregated record for samples collected so far and starts a new window.
+    pub fn flush(&mut self) -> Option<Stats> {
+        let start = self.start.take()?;
+        let mut stats = self.last.take()?;
+
+        stats.aggregate = Some(Aggregate {
+            samples: std::mem::take(&mut self.samples),
+            window_start: start,
+            cpu_percentage_min: self.cpu_percentage.min(),
+            cpu_percentage_max: self.cpu_percentage.max(),
+            cpu_percentage_avg: self.cpu_percentage.avg(),
+            memory_min: self.memory.min().map(|memory| memory as u64),
+            memory_max: self.memory.max().map(|memory| memory as u64),
+            memory_avg: self.memory.avg(),
+            memory_percentage_min: self.memory_percentage.min(),
+            memory_percentage_max: self.memory_percentage.max(),
+            memory_percentage_avg: self.memory_percentage.avg(),
+            pid_min: self.pid.min().map(|pid| pid as u64),
+            pid_max: self.pid.max().map(|pid| pid as u64),
+            pid_avg: self.pid.avg(),
+        });
+
+        self.cpu_percentage = Summary::default();
+        self.memory = Summary::default();
+        self.memory_percentage = Summary::default();
+        self.pid = Summary::default();
+
+        Some(stats)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    min: Option<f64>,
+    max: Option<f64>,
+    sum: f64,
+    count: u32,
+}
+
+impl Summary {
+    fn add(&mut self, value: Option<f64>) {
+        if let Some(value) = value {
+            self.min = Some(self.min.map_or(value, |min| min.min(value)));
+            self.max = Some(self.max.map_or(value, |max| max.max(value)));
+            self.sum += value;
+            self.count += 1;
+        }
+    }
+
+    fn min(&self) -> Option<f64> {
+        self.min
+    }
+
+    fn max(&self) -> Option<f64> {
+        self.max
+    }
+
+    fn avg(&self) -> Option<f64> {
+        if self.count > 0 {
+            Some(self.sum / self.count as f64)
+        } else {
+            None
+        }
+    }
+}
+
+/// Window summary attached to stats records in aggregation mode.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Aggregate {
+    samples: u32,
+    #[serde(serialize_with = "serialize_timestamp")]
+    window_start: DateTime<Utc>,
+    cpu_percentage_min: Option<f64>,
+    cpu_percentage_max: Option<f64>,
+    cpu_percentage_avg: Option<f64>,
+    memory_min: Option<u64>,
+    memory_max: Option<u64>,
+    memory_avg: Option<f64>,
+    memory_percentage_min: Option<f64>,
+    memory_percentage_max: Option<f64>,
+    memory_percentage_avg: Option<f64>,
+    pid_min: Option<u64>,
+    pid_max: Option<u64>,
+    pid_avg: Option<f64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    interval: usize,
+    aggregate: bool,
+    window: usize,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            interval: 0,
+            aggregate: false,
+            window: 60,
+        }
+    }
+}
+
+impl SamplingConfig {
+    pub fn new(interval: usize, aggregate: bool, window: usize) -> Self {
+        Self {
+            interval,
+            aggregate,
+            window,
+        }
+    }
+
+    pub fn into_parts(self) -> (Duration, Option<Duration>) {
+        let window = if self.aggregate {
+            Some(Duration::from_secs(self.window as u64))
+        } else {
+            None
+        };
+
+        (Duration::from_secs(self.interval as u64), window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn stats(second: u32, cpu_percentage: f64, memory: u64) -> Stats {
+        Stats {
+            timestamp: Utc.ymd(2020, 5, 1).and_hms(12, 0, second),
+            id: "0123456789ab".to_string(),
+            name: "web".to_string(),
+            cpu_percentage: Some(cpu_percentage),
+            memory: Some(memory),
+            memory_percentage: Some(memory as f64 / 10.0),
+            memory_limit: None,
+            network_rx: None,
+            network_tx: None,
+            block_read: None,
+            block_write: None,
+            pid: None,
+            metadata: Default::default(),
+            aggregate: None,
+        }
+    }
+
+    #[test]
+    fn it_samples_at_interval() {
+        let mut sampler = Sampler::new(Duration::from_secs(5));
+
+        let accepted = (0..12)
+            .filter(|second| sampler.accept(&stats(*second, 0.0, 0)))
+            .collect::<Vec<_>>();
+
+        assert_eq!(accepted, vec![0, 5, 10]);
+    }
+
+    #[test]
+    fn it_accepts_every_sample_without_interval() {
+        let mut sampler = Sampler::new(Duration::from_secs(0));
+
+        assert!((0..5).all(|second| sampler.accept(&stats(second, 0.0, 0))));
+    }
+
+    #[test]
+    fn it_aggregates_samples_over_window() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(3));
+
+        assert_eq!(aggregator.add(stats(0, 10.0, 100)), None);
+        assert_eq!(aggregator.add(stats(1, 30.0, 300)), None);
+        assert_eq!(aggregator.add(stats(2, 20.0, 200)), None);
+
+        let record = aggregator.add(stats(3, 50.0, 500)).unwrap();
+        assert_eq!(record.timestamp, stats(2, 0.0, 0).timestamp);
+        assert_eq!(record.cpu_percentage, Some(20.0));
+
+        let aggregate = record.aggregate.unwrap();
+        assert_eq!(aggregate.samples, 3);
+        assert_eq!(aggregate.window_start, stats(0, 0.0, 0).timestamp);
+        assert_eq!(aggregate.cpu_percentage_min, Some(10.0));
+        assert_eq!(aggregate.cpu_percentage_max, Some(30.0));
+        assert_eq!(aggregate.cpu_percentage_avg, Some(20.0));
+        assert_eq!(aggregate.memory_min, Some(100));
+        assert_eq!(aggregate.memory_max, Some(300));
+        assert_eq!(aggregate.memory_avg, Some(200.0));
+        assert_eq!(aggregate.memory_percentage_max, Some(30.0));
+        assert_eq!(aggregate.pid_avg, None);
+
+        let record = aggregator.flush().unwrap();
+        let aggregate = record.aggregate.unwrap();
+        assert_eq!(aggregate.samples, 1);
+        assert_eq!(aggregate.cpu_percentage_avg, Some(50.0));
+
+        assert_eq!(aggregator.flush(), None);
+    }
+
+    #[test]
+    fn it_serializes_aggregate_fields() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(60));
+        aggregator.add(stats(0, 10.0, 100));
+
+        let json = serde_json::to_value(aggregator.flush().unwrap()).unwrap();
+
+        assert_eq!(json["timestamp"], "2020-05-01T12:00:00.000Z");
+        assert_eq!(json["window_start"], "2020-05-01T12:00:00.000Z");
+        assert_eq!(json["samples"], 1);
+        assert_eq!(json["cpu_percentage_max"], 10.0);
+        assert_eq!(json["memory_avg"], 100.0);
+    }
+}
@@ -0,0 +1,357 @@
+use std::{collections::BTreeMap, fmt::Write, net::SocketAddr, path::PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::future::{BoxFuture, FutureExt};
+use log::debug;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{
+    net::{UdpSocket, UnixDatagram},
+    sync::Mutex,
+};
+use url::Url;
+
+use crate::{packet, sink::Sink};
+
+/// Emits numeric fields of stats records as StatsD gauges over UDP or a unix
+/// datagram socket.
+pub struct StatsdClient {
+    socket: Mutex<Socket>,
+    address: String,
+    prefix: String,
+    tags: bool,
+    max_packet_size: usize,
+}
+
+enum Socket {
+    Udp(UdpSocket),
+    // not connected, so datagrams reach an agent which restarted and bound
+    // the path again, or started after docmon
+    Unix(UnixDatagram, PathBuf),
+}
+
+impl Socket {
+    async fn send(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Socket::Udp(socket) => socket.send(buf).await,
+            Socket::Unix(socket, path) => socket.send_to(buf, path).await,
+        }
+    }
+}
+
+impl StatsdClient {
+    pub fn new(config: StatsdConfig) -> Result<Self> {
+        let (address, prefix, tags, max_packet_size) = config.into_parts();
+        let url = Url::parse(&address)?;
+
+        let socket = match url.scheme() {
+            "udp" => {
+                let addr = url
+                    .socket_addrs(|| Some(8125))?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| anyhow!("unable to resolve {}", address))?;
+                let local: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = std::net::UdpSocket::bind(local)?;
+                socket.connect(addr)?;
+                socket.set_nonblocking(true)?;
+                Socket::Udp(UdpSocket::from_std(socket)?)
+            }
+            "unix" => Socket::Unix(UnixDatagram::unbound()?, PathBuf::from(url.path())),
+            scheme => return Err(anyhow!("unsupported statsd address scheme: {}", scheme)),
+        };
+
+        Ok(Self {
+            socket: Mutex::new(socket),
+            address,
+            prefix,
+            tags,
+            max_packet_size,
+        })
+    }
+
+    pub async fn send(&self, items: &[Value]) -> Result<()> {
+        let metrics = items
+            .iter()
+            .flat_map(|item| gauges(item, &self.prefix, self.tags))
+            .collect::<Vec<_>>();
+        if metrics.is_empty() {
+            return Ok(());
+        }
+
+        let packets = packet::pack(&metrics, self.max_packet_size);
+        debug!(
+            "sending {} metric(s) in {} packet(s)",
+            metrics.len(),
+            packets.len()
+        );
+
+        let mut socket = self.socket.lock().await;
+        for packet in packets {
+            socket
+                .send(packet.as_bytes())
+                .await
+                .with_context(|| format!("unable to send metrics to {}", self.address))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats every number of a serialized `Stats` record as a gauge. With
+/// `tags` the container name, image and labels are DogStatsD tags, otherwise
+/// the container name becomes part of the metric name.
+fn gauges(record: &Value, prefix: &str, tags: bool) -> Vec<String> {
+    let record = match record.as_object() {
+        Some(record) => record,
+        None => return Vec::new(),
+    };
+    let name = record
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    let mut prefix = prefix.to_string();
+    let mut suffix = String::new();
+    if tags {
+        let mut pairs = BTreeMap::new();
+        if let Some(Value::Object(labels)) = record.get("labels") {
+            for (key, value) in labels {
+                if let Some(value) = value.as_str() {
+                    pairs.insert(key.as_str(), value);
+                }
+            }
+        }
+        // container properties win over labels with the same key
+        pairs.insert("container_name", name);
+        if let Some(image) = record.get("image").and_then(Value::as_str) {
+            pairs.insert("image_name", image);
+        }
+
+        let pairs = pairs
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, value)| format!("{}:{}", sanitize_tag(key), sanitize_tag(value)))
+            .collect::<Vec<_>>();
+        if !pairs.is_empty() {
+            suffix = format!("|#{}", pairs.join(","));
+        }
+    } else if !name.is_empty() {
+        append(&mut prefix, &sanitize_name(name));
+    }
+
+    record
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = value.as_f64()?;
+            let mut metric = prefix.clone();
+            append(&mut metric, key);
+            let _ = writeln!(metric, ":{}|g{}", value, suffix);
+            Some(metric)
+        })
+        .collect()
+}
+
+fn append(name: &mut String, segment: &str) {
+    if !name.is_empty() {
+        name.push('.');
+    }
+    name.push_str(segment);
+}
+
+// metric names are dot separated, so dots of container names are replaced too
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn sanitize_tag(tag: &str) -> String {
+    tag.chars()
+        .map(|c| match c {
+            ',' | '|' | '#' | '@' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StatsdConfig {
+    #[serde(default = "default_address")]
+    address: String,
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    #[serde(default = "default_tags")]
+    tags: bool,
+    #[serde(default = "default_max_packet_size")]
+    max_packet_size: usize,
+}
+
+fn default_address() -> String {
+    "udp://127.0.0.1:8125".to_string()
+}
+
+fn default_prefix() -> String {
+    "docker.container".to_string()
+}
+
+fn default_tags() -> bool {
+    true
+}
+
+fn default_max_packet_size() -> usize {
+    1432
+}
+
+impl StatsdConfig {
+    /// Creates config for `udp://host:port` or `unix:///path/to/socket`.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            prefix: default_prefix(),
+            tags: default_tags(),
+            max_packet_size: default_max_packet_size(),
+        }
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    pub fn with_tags(mut self, tags: bool) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    pub fn into_parts(self) -> (String, String, bool, usize) {
+        (self.address, self.prefix, self.tags, self.max_packet_size)
+    }
+}
+
+impl Sink for StatsdClient {
+    fn send<'a>(&'a self, _: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        StatsdClient::send(self, items).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn record() -> Value {
+        json!({
+            "timestamp": "2020-05-01T10:00:01.123Z",
+            "id": "0123456789ab",
+            "name": "web.1",
+            "cpu_percentage": 20.5,
+            "memory": 1024,
+            "memory_percentage": null,
+            "image": "nginx:1.17",
+            "labels": { "tier": "front,end", "container_name": "ignored" },
+        })
+    }
+
+    #[test]
+    fn it_formats_gauges_with_tags() {
+        let tags = "|#container_name:web.1,image_name:nginx:1.17,tier:front_end\n";
+
+        assert_eq!(
+            gauges(&record(), "docker.container", true),
+            vec![
+                format!("docker.container.cpu_percentage:20.5|g{}", tags),
+                format!("docker.container.memory:1024|g{}", tags),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_formats_gauges_without_tags() {
+        assert_eq!(
+            gauges(&record(), "", false),
+            vec![
+                "web_1.cpu_percentage:20.5|g\n".to_string(),
+                "web_1.memory:1024|g\n".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn it_sends_gauges_over_udp() {
+        let mut server = UdpSocket::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let address = format!("udp://{}", server.local_addr().unwrap());
+
+        let client = StatsdClient::new(StatsdConfig::new(address).with_prefix("docmon")).unwrap();
+        client.send(&[record(), record()]).await.unwrap();
+
+        let mut buf = [0; 1500];
+        let len = server.recv(&mut buf).await.unwrap();
+        let packet = std::str::from_utf8(&buf[..len]).unwrap();
+        assert_eq!(packet.lines().count(), 4);
+        assert!(packet.starts_with("docmon.cpu_percentage:20.5|g|#container_name:web.1,"));
+    }
+
+    #[tokio::test]
+    async fn it_sends_gauges_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dsd.socket");
+        let mut server = UnixDatagram::bind(&path).unwrap();
+        let address = format!("unix://{}", path.display());
+
+        let config = StatsdConfig::new(address).with_max_packet_size(100);
+        let client = StatsdClient::new(config).unwrap();
+        client.send(&[record()]).await.unwrap();
+
+        let mut buf = [0; 1500];
+        let len = server.recv(&mut buf).await.unwrap();
+        assert!(std::str::from_utf8(&buf[..len])
+            .unwrap()
+            .starts_with("docker.container.cpu_percentage:20.5|g|#"));
+        let len = server.recv(&mut buf).await.unwrap();
+        assert!(std::str::from_utf8(&buf[..len])
+            .unwrap()
+            .starts_with("docker.container.memory:1024|g|#"));
+    }
+
+    #[tokio::test]
+    async fn it_reaches_restarted_agent_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dsd.socket");
+        let address = format!("unix://{}", path.display());
+
+        // the agent is not listening yet
+        let client = StatsdClient::new(StatsdConfig::new(address)).unwrap();
+        assert!(client.send(&[record()]).await.is_err());
+
+        let mut buf = [0; 1500];
+        for _ in 0..2 {
+            let mut server = UnixDatagram::bind(&path).unwrap();
+            client.send(&[record()]).await.unwrap();
+
+            let len = server.recv(&mut buf).await.unwrap();
+            assert!(std::str::from_utf8(&buf[..len])
+                .unwrap()
+                .starts_with("docker.container.cpu_percentage:20.5|g|#"));
+
+            drop(server);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{self, Write};
+
+use anyhow::Result;
+use futures_util::future::{BoxFuture, FutureExt};
+use log::info;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::sink::Sink;
+
+/// Prints every batch to stdout as a JSON array instead of sending it
+/// anywhere, e.g. to check filters and metadata on a new host.
+pub struct StdoutWriter {
+    pretty: bool,
+}
+
+impl StdoutWriter {
+    pub fn new(config: StdoutConfig) -> Self {
+        Self {
+            pretty: config.into_parts(),
+        }
+    }
+
+    pub fn write(&self, log_name: &str, items: &[Value]) -> Result<()> {
+        info!("batch of {} record(s) for {}", items.len(), log_name);
+
+        let data = self.format(items)?;
+        let stdout = io::stdout();
+        let mut stdout = stdout.lock();
+        stdout.write_all(&data)?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    fn format(&self, items: &[Value]) -> Result<Vec<u8>> {
+        let mut data = if self.pretty {
+            serde_json::to_vec_pretty(items)?
+        } else {
+            serde_json::to_vec(items)?
+        };
+        data.push(b'\n');
+        Ok(data)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct StdoutConfig {
+    pretty: bool,
+}
+
+impl StdoutConfig {
+    pub fn new(pretty: bool) -> Self {
+        Self { pretty }
+    }
+
+    pub fn into_parts(self) -> bool {
+        self.pretty
+    }
+}
+
+impl Sink for StdoutWriter {
+    fn send<'a>(&'a self, log_name: &'a str, items: &'a [Value]) -> BoxFuture<'a, Result<()>> {
+        futures_util::future::ready(self.write(log_name, items)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn it_formats_batches_as_compact_or_pretty_json() {
+        let items = vec![json!({"id": "0123456789ab", "cpu_percentage": 20.5})];
+
+        let compact = StdoutWriter::new(StdoutConfig::new(false))
+            .format(&items)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(compact).unwrap(),
+            "[{\"cpu_percentage\":20.5,\"id\":\"0123456789ab\"}]\n"
+        );
+
+        let pretty = StdoutWriter::new(StdoutConfig::new(true))
+            .format(&items)
+            .unwrap();
+        let pretty = String::from_utf8(pretty).unwrap();
+        assert!(pretty.contains("\n    \"cpu_percentage\": 20.5,\n"));
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap(),
+            json!(items)
+        );
+    }
+}
@@ -0,0 +1,426 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use hyper::{body, Body, Method, Request};
+use hyper_tls::HttpsConnector;
+use log::debug;
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::Signer,
+    x509::X509,
+};
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+use url::form_urlencoded;
+
+use crate::{
+    client::ResponseError,
+    proxy::{Proxy, ProxyConnector},
+};
+
+const CLIENT_ASSERTION_TYPE: &str = "urn:ietf:params:oauth:client-assertion-type:jwt-bearer";
+const ASSERTION_LIFETIME: i64 = 10 * 60;
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Acquires Microsoft Entra ID access tokens with the OAuth2 client
+/// credentials flow and caches them until shortly before they expire.
+pub struct TokenProvider {
+    url: String,
+    client_id: String,
+    scope: String,
+    credential: Credential,
+    client: hyper::Client<HttpsConnector<ProxyConnector>, Body>,
+    token: Mutex<Option<Token>>,
+}
+
+enum Credential {
+    Secret(String),
+    Certificate {
+        key: PKey<Private>,
+        thumbprint: String,
+    },
+}
+
+struct Token {
+    value: String,
+    refresh_at: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl TokenProvider {
+    pub fn new(config: CredentialConfig, scope: impl Into<String>) -> Result<Self> {
+        let (authority, tenant_id, client_id, client_secret, certificate, private_key) =
+            config.into_parts();
+
+        let credential = match (client_secret, certificate) {
+            (Some(secret), None) => Credential::Secret(secret),
+            (None, Some(certificate)) => {
+                let pem = fs::read(&certificate).with_context(|| {
+                    format!("unable to read certificate {}", certificate.display())
+                })?;
+                let key = match private_key {
+                    Some(path) => fs::read(&path).with_context(|| {
+                        format!("unable to read private key {}", path.display())
+                    })?,
+                    None => pem.clone(),
+                };
+
+                Credential::Certificate {
+                    key: PKey::private_key_from_pem(&key)?,
+                    thumbprint: thumbprint(&X509::from_pem(&pem)?)?,
+                }
+            }
+            _ => {
+                return Err(anyhow!(
+                    "either client_secret or certificate is required for {}",
+                    client_id
+                ))
+            }
+        };
+
+        let url = format!(
+            "{}/{}/oauth2/v2.0/token",
+            authority.trim_end_matches('/'),
+            tenant_id
+        );
+        let proxy = Proxy::for_url(None, &url)?;
+        let client = hyper::Client::builder().build(ProxyConnector::https(proxy)?);
+
+        Ok(Self {
+            url,
+            client_id,
+            scope: scope.into(),
+            credential,
+            client,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Returns a cached token or requests a new one when it is about to expire.
+    pub async fn token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+
+        match token.as_ref() {
+            Some(token) if Instant::now() < token.refresh_at => Ok(token.value.clone()),
+            _ => {
+                let fresh = self.request().await?;
+                let value = fresh.value.clone();
+                *token = Some(fresh);
+                Ok(value)
+            }
+        }
+    }
+
+    /// Drops the cached token, e.g. after it was rejected.
+    pub async fn invalidate(&self) {
+        self.token.lock().await.take();
+    }
+
+    async fn request(&self) -> Result<Token> {
+        debug!("requesting access token for {}", self.client_id);
+
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri(&self.url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(self.form()?))?;
+
+        let requested = Instant::now();
+        let res = self.client.request(req).await?;
+
+        // invalid credentials are answered with 400 or 401, which are not
+        // retried, while throttling and outages are
+        if !res.status().is_success() {
+            return Err(anyhow::Error::new(ResponseError::from_response(res).await?)
+                .context("unable to get access token"));
+        }
+
+        let bytes = body::to_bytes(res.into_body()).await?;
+
+        let res: TokenResponse = serde_json::from_slice(&bytes)?;
+        let lifetime = Duration::from_secs(res.expires_in);
+        let margin = REFRESH_MARGIN.min(lifetime / 2);
+
+        Ok(Token {
+            value: res.access_token,
+            refresh_at: requested + lifetime - margin,
+        })
+    }
+
+    fn form(&self) -> Result<String> {
+        let mut form = form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "client_credentials")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("scope", &self.scope);
+
+        match &self.credential {
+            Credential::Secret(secret) => {
+                form.append_pair("client_secret", secret);
+            }
+            Credential::Certificate { key, thumbprint } => {
+                form.append_pair("client_assertion_type", CLIENT_ASSERTION_TYPE)
+                    .append_pair("client_assertion", &self.assertion(key, thumbprint)?);
+            }
+        }
+
+        Ok(form.finish())
+    }
+
+    // a JWT signed with the certificate key, see
+    // https://learn.microsoft.com/entra/identity-platform/certificate-credentials
+    fn assertion(&self, key: &PKey<Private>, thumbprint: &str) -> Result<String> {
+        let now = Utc::now().timestamp();
+        let jti = format!("{:032x}", rand::thread_rng().gen::<u128>());
+
+        let header = json!({ "alg": "RS256", "typ": "JWT", "x5t": thumbprint });
+        let claims = json!({
+            "aud": self.url,
+            "iss": self.client_id,
+            "sub": self.client_id,
+            "jti": jti,
+            "nbf": now,
+            "exp": now + ASSERTION_LIFETIME,
+        });
+
+        let content = format!(
+            "{}.{}",
+            base64url(serde_json::to_vec(&header)?),
+            base64url(serde_json::to_vec(&claims)?)
+        );
+
+        let mut signer = Signer::new(MessageDigest::sha256(), key)?;
+        signer.update(content.as_bytes())?;
+        let signature = signer.sign_to_vec()?;
+
+        Ok(format!("{}.{}", content, base64url(signature)))
+    }
+}
+
+fn thumbprint(certificate: &X509) -> Result<String> {
+    Ok(base64url(certificate.digest(MessageDigest::sha1())?))
+}
+
+fn base64url(data: impl AsRef<[u8]>) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CredentialConfig {
+    #[serde(default = "default_authority")]
+    authority: String,
+    tenant_id: String,
+    client_id: String,
+    client_secret: Option<String>,
+    certificate: Option<PathBuf>,
+    private_key: Option<PathBuf>,
+}
+
+fn default_authority() -> String {
+    "https://login.microsoftonline.com".to_string()
+}
+
+impl CredentialConfig {
+    pub fn with_secret(
+        tenant_id: impl Into<String>,
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+    ) -> Self {
+        Self {
+            authority: default_authority(),
+            tenant_id: tenant_id.into(),
+            client_id: client_id.into(),
+            client_secret: Some(client_secret.into()),
+            certificate: None,
+            private_key: None,
+        }
+    }
+
+    pub fn with_certificate(
+        tenant_id: impl Into<String>,
+        client_id: impl Into<String>,
+        certificate: impl Into<PathBuf>,
+        private_key: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            authority: default_authority(),
+            tenant_id: tenant_id.into(),
+            client_id: client_id.into(),
+            client_secret: None,
+            certificate: Some(certificate.into()),
+            private_key,
+        }
+    }
+
+    pub fn with_authority(mut self, authority: impl Into<String>) -> Self {
+        self.authority = authority.into();
+        self
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn into_parts(
+        self,
+    ) -> (
+        String,
+        String,
+        String,
+        Option<String>,
+        Option<PathBuf>,
+        Option<PathBuf>,
+    ) {
+        (
+            self.authority,
+            self.tenant_id,
+            self.client_id,
+            self.client_secret,
+            self.certificate,
+            self.private_key,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Response, StatusCode};
+    use openssl::{
+        asn1::Asn1Time, bn::BigNum, hash::MessageDigest, nid::Nid, rsa::Rsa, sign::Verifier,
+        x509::X509Name,
+    };
+
+    use super::*;
+    use crate::{
+        mock::RecordingServer,
+        retry::{self, Failure},
+    };
+
+    // serves tokens which expire in `expires_in` seconds
+    fn token_server(expires_in: u64) -> RecordingServer {
+        RecordingServer::start(move |_, earlier| {
+            let body = json!({
+                "token_type": "Bearer",
+                "expires_in": expires_in,
+                "access_token": format!("token-{}", earlier.len()),
+            });
+            Response::new(Body::from(body.to_string()))
+        })
+    }
+
+    #[tokio::test]
+    async fn it_caches_token_until_it_expires() {
+        let server = token_server(3600);
+        let config = CredentialConfig::with_secret("tenant", "client", "secret")
+            .with_authority(server.url());
+        let provider = TokenProvider::new(config, "scope").unwrap();
+
+        assert_eq!(provider.token().await.unwrap(), "token-0");
+        assert_eq!(provider.token().await.unwrap(), "token-0");
+        assert_eq!(server.requests().len(), 1);
+
+        let form = String::from_utf8(server.requests()[0].body.clone()).unwrap();
+        assert!(form.contains("grant_type=client_credentials"));
+        assert!(form.contains("client_secret=secret"));
+
+        provider.invalidate().await;
+        assert_eq!(provider.token().await.unwrap(), "token-1");
+    }
+
+    #[tokio::test]
+    async fn it_does_not_retry_invalid_credentials() {
+        let server = RecordingServer::start(|_, _| {
+            let body = json!({ "error": "invalid_client" }).to_string();
+            let mut res = Response::new(Body::from(body));
+            *res.status_mut() = StatusCode::UNAUTHORIZED;
+            res
+        });
+
+        let config =
+            CredentialConfig::with_secret("tenant", "client", "wrong").with_authority(server.url());
+        let provider = TokenProvider::new(config, "scope").unwrap();
+
+        let e = provider.token().await.unwrap_err();
+        assert!(e.to_string().contains("access token"));
+        assert_eq!(retry::classify(&e), Failure::Permanent);
+    }
+
+    #[tokio::test]
+    async fn it_refreshes_token_before_it_expires() {
+        let server = token_server(0);
+        let config = CredentialConfig::with_secret("tenant", "client", "secret")
+            .with_authority(server.url());
+        let provider = TokenProvider::new(config, "scope").unwrap();
+
+        provider.token().await.unwrap();
+        provider.token().await.unwrap();
+
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn it_signs_client_assertion_with_certificate() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "docmon").unwrap();
+        let name = name.build();
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_serial_number(&BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap())
+            .unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+        let cert = cert.build();
+
+        let dir = tempfile::tempdir().unwrap();
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        fs::write(&cert_path, cert.to_pem().unwrap()).unwrap();
+        fs::write(&key_path, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+
+        let config =
+            CredentialConfig::with_certificate("tenant", "client", cert_path, Some(key_path));
+        let provider = TokenProvider::new(config, "scope").unwrap();
+        let (key, thumbprint) = match &provider.credential {
+            Credential::Certificate { key, thumbprint } => (key, thumbprint),
+            Credential::Secret(_) => panic!("expected certificate credential"),
+        };
+
+        let assertion = provider.assertion(key, thumbprint).unwrap();
+        let parts = assertion.split('.').collect::<Vec<_>>();
+        assert_eq!(parts.len(), 3);
+
+        let decode = |part| base64::decode_config(part, base64::URL_SAFE_NO_PAD).unwrap();
+        let header: serde_json::Value = serde_json::from_slice(&decode(parts[0])).unwrap();
+        let claims: serde_json::Value = serde_json::from_slice(&decode(parts[1])).unwrap();
+        assert_eq!(header["alg"], "RS256");
+        assert_eq!(header["x5t"], thumbprint.as_str());
+        assert_eq!(
+            claims["aud"],
+            "https://login.microsoftonline.com/tenant/oauth2/v2.0/token"
+        );
+        assert_eq!(claims["sub"], "client");
+
+        let public_key = cert.public_key().unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key).unwrap();
+        verifier
+            .update(format!("{}.{}", parts[0], parts[1]).as_bytes())
+            .unwrap();
+        assert!(verifier.verify(&decode(parts[2])).unwrap());
+    }
+}
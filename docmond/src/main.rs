@@ -1,20 +1,28 @@
+use std::time::Duration;
+
 use anyhow::{Context, Result};
-use bollard::Docker;
+use bollard::{Docker, API_DEFAULT_VERSION};
 use clap::{app_from_crate, crate_authors, crate_description, crate_name, crate_version, Arg};
-use docmon::{Client, Collector, Config, Publisher};
+use docmon::{Collector, Config, Publisher, PublisherHandle, Router};
 use env_logger::{Builder, Env};
 use futures_util::{
     future::{self, Either},
     pin_mut, StreamExt,
 };
 use log::info;
-use tokio::signal::unix::{signal, SignalKind};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time,
+};
+
+// how often the counters of every output are logged
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
 
 #[tokio::main]
 async fn main() -> Result<()> {
     Builder::from_env(Env::new().filter_or("DOCMON_LOG", "info")).init();
 
-    let config = app_from_crate!()
+    let matches = app_from_crate!()
         .arg(
             Arg::with_name("config")
                 .short("c")
@@ -24,31 +32,88 @@ async fn main() -> Result<()> {
                 .takes_value(true)
                 .default_value("/etc/docmon/config.toml"),
         )
-        .get_matches()
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Prints batches to stdout instead of sending them to the outputs"),
+        )
+        .arg(
+            Arg::with_name("pretty")
+                .long("pretty")
+                .requires("dry-run")
+                .help("Pretty prints batches in dry run mode"),
+        )
+        .get_matches();
+
+    let config = matches
         .value_of("config")
         .map(Config::from_file)
         .transpose()?
         .expect("config");
 
-    let (client_config, publisher_config) = config.into_parts();
+    let (mut outputs, collector_config) = config.into_parts();
+    if matches.is_present("dry-run") {
+        info!("dry run, batches are printed to stdout");
+        let pretty = matches.is_present("pretty");
+        outputs = outputs
+            .into_iter()
+            .map(|output| output.into_dry_run(pretty))
+            .collect();
+    }
+
+    let mut router = Router::new();
+    let mut publisher_handles = Vec::with_capacity(outputs.len());
+    let mut join_handles = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        let (sink, publisher_config, filter) = output.into_parts();
+        let (publisher, publisher_handle) = Publisher::new(sink.build()?, publisher_config)?;
+        publisher_handles.push(publisher_handle.clone());
+        router = router.route(publisher_handle, filter)?;
+        join_handles.push(tokio::spawn(publisher.run()));
+    }
 
-    let client = Client::new(client_config).unwrap();
-    let (publisher, publisher_handle) = Publisher::new(client, publisher_config);
-    let join_handle = tokio::spawn(publisher.run());
+    tokio::spawn(report(publisher_handles.clone()));
 
-    let docker = Docker::connect_with_unix_defaults()
-        .with_context(|| "unable to connect to docker daemon")?;
+    let docker =
+        Docker::connect_with_unix(collector_config.docker_socket(), 120, API_DEFAULT_VERSION)
+            .with_context(|| "unable to connect to docker daemon")?;
 
     let shutdown_signal = shutdown();
     pin_mut!(shutdown_signal);
 
-    let collector = Collector::new(docker, publisher_handle);
+    let collector = Collector::new(docker, router, collector_config)?;
     collector.run(shutdown_signal).await;
 
-    join_handle.await?;
+    // stats streams may still hold handles, so publishers are stopped explicitly
+    for publisher_handle in publisher_handles {
+        publisher_handle.stop();
+    }
+    for join_handle in join_handles {
+        join_handle.await?;
+    }
     Ok(())
 }
 
+async fn report<D>(publisher_handles: Vec<PublisherHandle<D>>)
+where
+    D: std::fmt::Debug,
+{
+    let mut interval = time::interval_at(time::Instant::now() + REPORT_INTERVAL, REPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        for (output, publisher_handle) in publisher_handles.iter().enumerate() {
+            info!(
+                "output {}: {} record(s) queued, {} dropped, {} split, {} truncated",
+                output,
+                publisher_handle.queued(),
+                publisher_handle.dropped(),
+                publisher_handle.split(),
+                publisher_handle.truncated()
+            );
+        }
+    }
+}
+
 async fn shutdown() {
     let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM signal handling failure");
     let mut interrupt = signal(SignalKind::interrupt()).expect("SIGINT signal handling failure");